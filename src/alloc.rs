@@ -0,0 +1,86 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::flow;
+use crate::parse::expr::{Label, VarStorage, IR};
+
+/// Replaces the temporary registers code generation hands out with as few variables as possible, all starting with `prefix`.
+/// Two registers share a variable unless one is still needed when the other is written.
+///
+/// The main program and every function are allocated separately, and each function's variables are named after it,
+/// so a call never overwrites the temporaries of the code calling it. Must run before `IR::resolve_labels`.
+pub fn allocate_registers(mut ir: Vec<IR>, prefix: &str) -> Vec<IR> {
+    let mut start = 0;
+    let mut function: Option<String> = None;
+    for idx in 0..=ir.len() {
+        let next_function = match ir.get(idx) {
+            Some(IR::Label(Label::Function(fname))) => Some(fname.clone()),
+            Some(_) => continue,
+            None => None,
+        };
+        let names = allocate_region(&ir[start..idx]);
+        for operand in ir[start..idx].iter_mut().flat_map(IR::operands_mut) {
+            if let Some(register) = VarStorage::register_index(operand) {
+                *operand = match &function {
+                    Some(fname) => format!("{prefix}{fname}.{}", names[&register]),
+                    None => format!("{prefix}{}", names[&register]),
+                };
+            }
+        }
+        start = idx;
+        function = next_function;
+    }
+    ir
+}
+
+/// Picks a number for every register in a stretch of IR that's entered at its start, so that registers that are
+/// live at the same time get different numbers.
+fn allocate_region(ir: &[IR]) -> HashMap<usize, usize> {
+    let is_register = |name: &str| VarStorage::register_index(name).is_some();
+    let successors = flow::successors(ir);
+    let live_out = flow::live_out(ir, &successors, is_register);
+    let index = |name: &String| VarStorage::register_index(name).expect("only registers are tracked");
+
+    let mut interferes: HashMap<usize, HashSet<usize>> = HashMap::new();
+    let mut add_edge = |a: usize, b: usize| {
+        if a != b {
+            interferes.entry(a).or_default().insert(b);
+            interferes.entry(b).or_default().insert(a);
+        }
+    };
+    let mut registers = vec![];
+    for (idx, fragment) in ir.iter().enumerate() {
+        let (def, uses) = fragment.def_use();
+        registers.extend(def.into_iter().chain(uses).filter_map(|name| VarStorage::register_index(name)));
+        if let Some(def) = def.filter(|def| is_register(def)) {
+            for live in &live_out[idx] {
+                add_edge(index(def), index(live));
+            }
+        }
+    }
+    // Registers read before they're written all hold whatever they held coming in
+    if let Some(first) = ir.first() {
+        let mut entry = live_out[0].iter().map(index).collect::<HashSet<usize>>();
+        let (def, uses) = first.def_use();
+        if let Some(def) = def.filter(|def| is_register(def)) {
+            entry.remove(&index(def));
+        }
+        entry.extend(uses.into_iter().filter(|used| is_register(used)).map(index));
+        for &a in &entry {
+            for &b in &entry {
+                add_edge(a, b);
+            }
+        }
+    }
+
+    registers.sort_unstable();
+    registers.dedup();
+    let mut names: HashMap<usize, usize> = HashMap::new();
+    for register in registers {
+        let taken = interferes.get(&register).into_iter().flatten()
+            .filter_map(|other| names.get(other))
+            .collect::<HashSet<&usize>>();
+        let name = (0..).find(|name| !taken.contains(name)).expect("there is always a free name");
+        names.insert(register, name);
+    }
+    names
+}
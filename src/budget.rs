@@ -0,0 +1,70 @@
+use crate::error::CompileError;
+use crate::parse::expr::{Label, IR};
+use crate::span::Span;
+
+/// How many instructions a Mindustry processor holds.
+pub const DEFAULT_LIMIT: usize = 1000;
+/// How many of the largest statements of the main program and each function are listed when the program is too long.
+const LISTED_STATEMENTS: usize = 5;
+
+/// The instructions in the main program or a function, and in each of its statements.
+struct Part {
+    name: String,
+    size: usize,
+    statements: Vec<(Span, usize)>,
+}
+
+/// Fails if the program is more than `limit` instructions long, listing the size of the main program, each function
+/// and their largest statements so it's clear what to cut down. The error points at the largest statement.
+/// Must run before `IR::resolve_labels`, which removes the labels marking where functions and statements start.
+pub fn check_size(ir: &[IR], limit: usize) -> Result<(), Vec<CompileError>> {
+    let total = ir.iter().filter(|fragment| !matches!(fragment, IR::Label(_))).count();
+    if total <= limit {
+        return Ok(());
+    }
+
+    let mut parts = vec![Part { name: "main program".to_string(), size: 0, statements: vec![] }];
+    let mut in_statement = false;
+    for fragment in ir {
+        let part = parts.last_mut().expect("there is always a part being counted");
+        match fragment {
+            IR::Label(Label::Function(fname)) => {
+                parts.push(Part { name: format!("fn {fname}"), size: 0, statements: vec![] });
+                in_statement = false;
+            }
+            IR::Label(Label::Statement(Some(span))) => {
+                part.statements.push((*span, 0));
+                in_statement = true;
+            }
+            IR::Label(Label::Statement(None)) => in_statement = false,
+            IR::Label(_) => (),
+            _ => {
+                part.size += 1;
+                if let Some((_, size)) = part.statements.last_mut().filter(|_| in_statement) {
+                    *size += 1;
+                }
+            }
+        }
+    }
+
+    let largest = parts.iter()
+        .flat_map(|part| &part.statements)
+        .max_by_key(|(_, size)| *size)
+        .map_or(Span::default(), |(span, _)| *span);
+    parts.sort_by_key(|part| std::cmp::Reverse(part.size));
+    let mut message = format!(
+        "the program is {total} instructions long, but a processor holds at most {limit} (the limit can be changed with `--max-instructions`)"
+    );
+    for part in &mut parts {
+        message.push_str(&format!("\n  {}: {}", part.name, plural(part.size)));
+        part.statements.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+        for (span, size) in part.statements.iter().take(LISTED_STATEMENTS) {
+            message.push_str(&format!("\n    line {}:{}: {}", span.line, span.col, plural(*size)));
+        }
+    }
+    Err(vec![CompileError::Codegen(largest, message)])
+}
+
+fn plural(size: usize) -> String {
+    format!("{size} instruction{}", if size == 1 { "" } else { "s" })
+}
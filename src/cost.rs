@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::flow;
+use crate::parse::expr::{Label, IR};
+
+/// The processors Mindustry has and how many instructions each runs per tick.
+pub const PROCESSORS: &[(&str, f64)] = &[("micro", 2.0), ("logic", 8.0), ("hyper", 25.0)];
+
+/// A number of instructions, and what else runs that takes an unknown amount of time.
+#[derive(Default, Clone)]
+struct Cost {
+    instructions: usize,
+    unknown: Vec<String>,
+}
+impl Cost {
+    fn add(&mut self, other: &Cost) {
+        self.instructions += other.instructions;
+        for reason in &other.unknown {
+            if !self.unknown.contains(reason) {
+                self.unknown.push(reason.clone());
+            }
+        }
+    }
+    /// The cost in instructions and in ticks on each processor.
+    fn describe(&self) -> String {
+        let ticks = PROCESSORS.iter()
+            .map(|(name, per_tick)| format!("{name} {:.2}", self.instructions as f64 / per_tick))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut out = format!("{} instruction{} ({ticks} ticks)", self.instructions, if self.instructions == 1 { "" } else { "s" });
+        if !self.unknown.is_empty() {
+            out.push_str(&format!(" plus {}", self.unknown.join(", ")));
+        }
+        out
+    }
+}
+
+/// Instructions that always run one after the other, from `start` up to `end` in the IR.
+struct Block {
+    start: usize,
+    end: usize,
+    cost: Cost,
+    /// Functions called from the block, once for every call, whose cost is added on top.
+    calls: Vec<String>,
+    successors: Vec<usize>,
+}
+
+/// The main program or a function.
+struct Region<'a> {
+    name: String,
+    ir: &'a [IR],
+    /// The line of the first instruction in the output.
+    first_line: usize,
+    blocks: Vec<Block>,
+}
+
+/// Estimates how long the program takes to run, for the `--cost` report: the size of every basic block and loop,
+/// and how many instructions and ticks one pass of the main program and one call of each function take
+/// on each processor, taking the longest way through and going around each loop once.
+/// `wait`, other inline logic and recursive calls are listed as taking an unknown amount of time.
+/// Takes the IR with its labels, like `budget::check_size`.
+pub fn report(ir: &[IR]) -> String {
+    let mut regions = vec![];
+    let (mut start, mut first_line) = (0, 0);
+    let mut name = "main program".to_string();
+    for idx in 0..=ir.len() {
+        let next_name = match ir.get(idx) {
+            Some(IR::Label(Label::Function(fname))) => format!("fn {fname}"),
+            Some(_) => continue,
+            None => String::new(),
+        };
+        let region = Region::new(std::mem::replace(&mut name, next_name), &ir[start..idx], first_line);
+        first_line += region.blocks.iter().map(|block| block.cost.instructions).sum::<usize>();
+        regions.push(region);
+        start = idx;
+    }
+
+    let mut passes = HashMap::new();
+    for idx in 0..regions.len() {
+        pass_cost(&regions, idx, &mut passes, &mut vec![]);
+    }
+    let mut out = String::new();
+    for (idx, region) in regions.iter().enumerate() {
+        let what = if idx == 0 { "one pass" } else { "one call" };
+        out.push_str(&format!("{}: {what} takes at most {}\n", region.name, passes[&idx].describe()));
+        for block in region.blocks.iter().filter(|block| block.cost.instructions > 0 || !block.cost.unknown.is_empty()) {
+            out.push_str(&format!("  block at {}: {}", region.lines(block.start, block.end), block.cost.describe()));
+            if !block.calls.is_empty() {
+                out.push_str(&format!(", calling {}", describe_calls(&block.calls)));
+            }
+            out.push('\n');
+        }
+        for (header, body) in region.loops() {
+            let mut cost = Cost::default();
+            for &block in &body {
+                cost.add(&region.block_cost(block, &regions, &passes));
+            }
+            let end = body.iter().map(|&block| region.blocks[block].end).max().unwrap_or(region.blocks[header].end);
+            out.push_str(&format!(
+                "  loop at {}: {} per iteration\n",
+                region.lines(region.blocks[header].start, end),
+                cost.describe(),
+            ));
+        }
+    }
+    out
+}
+
+/// The functions called, in the order they're first called, with how many times each is.
+fn describe_calls(calls: &[String]) -> String {
+    let mut names: Vec<&String> = vec![];
+    for name in calls {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names.iter()
+        .map(|&name| match calls.iter().filter(|&call| call == name).count() {
+            1 => name.clone(),
+            count => format!("{name} {count} times"),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// The cost of running through the region `idx` once, memoized in `passes`. `calling` holds the regions being costed,
+/// so recursion is caught.
+fn pass_cost(regions: &[Region], idx: usize, passes: &mut HashMap<usize, Cost>, calling: &mut Vec<usize>) -> Cost {
+    if let Some(cost) = passes.get(&idx) {
+        return cost.clone();
+    }
+    calling.push(idx);
+    let region = &regions[idx];
+    // The cost of each function called, which recursion leaves unknown
+    let mut callees = HashMap::new();
+    for name in region.blocks.iter().flat_map(|block| &block.calls) {
+        let Some(callee) = regions.iter().position(|other| other.name == format!("fn {name}")) else { continue };
+        let cost = if calling.contains(&callee) {
+            Cost { instructions: 0, unknown: vec![format!("recursion into `{name}`")] }
+        } else {
+            pass_cost(regions, callee, passes, calling)
+        };
+        callees.insert(name.clone(), cost);
+    }
+    let back_edges = region.back_edges();
+    // The longest way from each block to the end, ignoring jumps back to the start of a loop
+    let mut longest: Vec<Option<Cost>> = vec![None; region.blocks.len()];
+    for block in region.topological_order(&back_edges).into_iter().rev() {
+        let mut cost = region.blocks[block].cost.clone();
+        for call in &region.blocks[block].calls {
+            cost.add(&callees[call]);
+        }
+        let rest = region.blocks[block].successors.iter()
+            .filter(|&&succ| !back_edges.contains(&(block, succ)))
+            .filter_map(|&succ| longest[succ].clone())
+            .max_by_key(|rest| rest.instructions);
+        if let Some(rest) = rest {
+            cost.add(&rest);
+        }
+        longest[block] = Some(cost);
+    }
+    calling.pop();
+    let cost = longest.first().cloned().flatten().unwrap_or_default();
+    passes.insert(idx, cost.clone());
+    cost
+}
+
+impl<'a> Region<'a> {
+    fn new(name: String, ir: &'a [IR], first_line: usize) -> Region<'a> {
+        let successors = flow::successors(ir);
+        // A block starts at the first instruction, at every jump target and after every jump, `end` or return
+        let mut leaders = HashSet::from([0]);
+        for (idx, next) in successors.iter().enumerate() {
+            if next.as_slice() != [idx + 1] {
+                leaders.insert(idx + 1);
+                leaders.extend(next);
+            }
+        }
+        let mut leaders = leaders.into_iter().filter(|&leader| leader < ir.len()).collect::<Vec<usize>>();
+        leaders.sort_unstable();
+        let block_of = |idx: usize| leaders.partition_point(|&leader| leader <= idx) - 1;
+
+        let blocks = leaders.iter().enumerate().map(|(block, &start)| {
+            let end = leaders.get(block + 1).copied().unwrap_or(ir.len());
+            let mut cost = Cost::default();
+            let mut calls = vec![];
+            for fragment in &ir[start..end] {
+                match fragment {
+                    IR::Label(_) => continue,
+                    IR::InlineLogic(logic) if logic.split_whitespace().next() == Some("wait") => {
+                        cost.add(&Cost { instructions: 0, unknown: vec!["`wait`".to_string()] });
+                    }
+                    IR::InlineLogic(_) => cost.add(&Cost { instructions: 0, unknown: vec!["inline logic".to_string()] }),
+                    IR::Jump(Label::Function(fname), _) => calls.push(fname.clone()),
+                    _ => (),
+                }
+                cost.instructions += 1;
+            }
+            let successors = successors[end - 1].iter().map(|&succ| block_of(succ)).collect();
+            Block { start, end, cost, calls, successors }
+        }).collect();
+        Region { name, ir, first_line, blocks }
+    }
+
+    /// The output lines of the instructions from `start` to `end` in the region's IR.
+    fn lines(&self, start: usize, end: usize) -> String {
+        let line = |idx: usize| self.first_line + self.ir[..idx].iter().filter(|fragment| !matches!(fragment, IR::Label(_))).count();
+        let (first, last) = (line(start), line(end).saturating_sub(1).max(line(start)));
+        if first == last {
+            format!("line {first}")
+        } else {
+            format!("lines {first}-{last}")
+        }
+    }
+
+    /// The jumps from a block to one that always runs before it, which go around a loop.
+    fn back_edges(&self) -> HashSet<(usize, usize)> {
+        let count = self.blocks.len();
+        let mut dominators: Vec<HashSet<usize>> = vec![(0..count).collect(); count];
+        if count > 0 {
+            dominators[0] = HashSet::from([0]);
+        }
+        let mut predecessors = vec![vec![]; count];
+        for (block, Block { successors, .. }) in self.blocks.iter().enumerate() {
+            for &succ in successors {
+                predecessors[succ].push(block);
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in 1..count {
+                let mut common = predecessors[block].iter()
+                    .map(|&pred| dominators[pred].clone())
+                    .reduce(|a, b| a.intersection(&b).copied().collect())
+                    .unwrap_or_default();
+                common.insert(block);
+                if common != dominators[block] {
+                    dominators[block] = common;
+                    changed = true;
+                }
+            }
+        }
+        self.blocks.iter().enumerate()
+            .flat_map(|(block, Block { successors, .. })| successors.iter().map(move |&succ| (block, succ)))
+            .filter(|(block, succ)| dominators[*block].contains(succ))
+            .collect()
+    }
+
+    /// Every loop, as the block it starts at and the blocks in it, in the order they start.
+    fn loops(&self) -> Vec<(usize, Vec<usize>)> {
+        let mut loops: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for (tail, header) in self.back_edges() {
+            // The loop is every block that gets back to the tail without going through the header
+            let body = loops.entry(header).or_insert_with(|| HashSet::from([header]));
+            let mut stack = vec![tail];
+            while let Some(block) = stack.pop() {
+                if body.insert(block) {
+                    stack.extend(self.blocks.iter().enumerate()
+                        .filter(|(_, Block { successors, .. })| successors.contains(&block))
+                        .map(|(pred, _)| pred));
+                }
+            }
+        }
+        let mut loops = loops.into_iter()
+            .map(|(header, body)| {
+                let mut body = body.into_iter().collect::<Vec<usize>>();
+                body.sort_unstable();
+                (header, body)
+            })
+            .collect::<Vec<_>>();
+        loops.sort_unstable();
+        loops
+    }
+
+    /// The order to visit blocks in so that every block comes before the ones it jumps forward to.
+    fn topological_order(&self, back_edges: &HashSet<(usize, usize)>) -> Vec<usize> {
+        let mut order = vec![];
+        let mut visited = vec![false; self.blocks.len()];
+        fn visit(region: &Region, block: usize, back_edges: &HashSet<(usize, usize)>, visited: &mut [bool], order: &mut Vec<usize>) {
+            visited[block] = true;
+            for &succ in &region.blocks[block].successors {
+                if !visited[succ] && !back_edges.contains(&(block, succ)) {
+                    visit(region, succ, back_edges, visited, order);
+                }
+            }
+            order.push(block);
+        }
+        if !self.blocks.is_empty() {
+            visit(self, 0, back_edges, &mut visited, &mut order);
+        }
+        order.reverse();
+        order
+    }
+
+    /// The cost of a block, including the functions it calls.
+    fn block_cost(&self, block: usize, regions: &[Region], passes: &HashMap<usize, Cost>) -> Cost {
+        let mut cost = self.blocks[block].cost.clone();
+        for name in &self.blocks[block].calls {
+            if let Some(callee) = regions.iter().position(|other| other.name == format!("fn {name}")) {
+                cost.add(&passes[&callee]);
+            }
+        }
+        cost
+    }
+}
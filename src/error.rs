@@ -0,0 +1,80 @@
+use std::fmt::{Display, Formatter};
+
+use crate::span::Span;
+
+/// An error from one of the compiler's phases, pointing at the source that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// The script contains characters or literals that don't make up a token.
+    Lex(Span, String),
+    /// The tokens don't make up a statement or expression.
+    Parse(Span, String),
+    /// The script is well-formed but doesn't make sense, such as calling a function that doesn't exist.
+    Semantic(Span, String),
+    /// The AST couldn't be turned into logic.
+    Codegen(Span, String),
+}
+impl CompileError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Lex(span, _) | Self::Parse(span, _) | Self::Semantic(span, _) | Self::Codegen(span, _) => *span,
+        }
+    }
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Lex(_, msg) | Self::Parse(_, msg) | Self::Semantic(_, msg) | Self::Codegen(_, msg) => msg,
+        }
+    }
+    pub fn phase(&self) -> &'static str {
+        match self {
+            Self::Lex(..) => "lex",
+            Self::Parse(..) => "parse",
+            Self::Semantic(..) => "semantic",
+            Self::Codegen(..) => "codegen",
+        }
+    }
+    /// Renders the error rustc-style, quoting the offending line of `code` and underlining the span with carets.
+    pub fn render(&self, fname: &str, code: &str) -> String {
+        render_diagnostic(&format!("error[{}]", self.phase()), self.message(), self.span(), fname, code)
+    }
+}
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} error at {}: {}", self.phase(), self.span(), self.message())
+    }
+}
+impl std::error::Error for CompileError {}
+
+/// Something that's allowed but most likely a mistake. Warnings don't stop compilation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub span: Span,
+    pub message: String,
+}
+impl Warning {
+    pub fn new(span: Span, message: String) -> Warning {
+        Warning { span, message }
+    }
+    /// Renders the warning rustc-style, like `CompileError::render`.
+    pub fn render(&self, fname: &str, code: &str) -> String {
+        render_diagnostic("warning", &self.message, self.span, fname, code)
+    }
+}
+
+/// Formats a message with a header such as `error[parse]`, followed by the source line of `span` with the span underlined.
+pub fn render_diagnostic(header: &str, message: &str, span: Span, fname: &str, code: &str) -> String {
+    let line = code.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+    let gutter = " ".repeat(span.line.to_string().len());
+    let line_start = code[..span.start.min(code.len())].rfind('\n').map_or(0, |nl| nl + 1);
+    let underlined = code.get(span.start..span.end.min(line_start + line.len()))
+        .map_or(0, |s| s.chars().count())
+        .max(1);
+    format!(
+        "{header}: {message}\n{gutter}--> {fname}:{}:{}\n{gutter} |\n{} | {line}\n{gutter} | {}{}\n",
+        span.line,
+        span.col,
+        span.line,
+        " ".repeat(span.col.saturating_sub(1)),
+        "^".repeat(underlined),
+    )
+}
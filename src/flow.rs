@@ -0,0 +1,64 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parse::expr::{JumpCond, Label, IR};
+
+/// The instructions that can run right after each instruction in IR that still has its labels.
+///
+/// Calls are treated as coming straight back to the next instruction, so functions are separate from the code calling them.
+/// `end` and returns (`set @counter`) have no successors.
+pub fn successors(ir: &[IR]) -> Vec<Vec<usize>> {
+    let labels = ir.iter().enumerate()
+        .filter_map(|(idx, fragment)| match fragment {
+            IR::Label(label) => Some((label, idx)),
+            _ => None,
+        })
+        .collect::<HashMap<&Label, usize>>();
+    (0..ir.len()).map(|idx| {
+        let next = Some(idx + 1).filter(|&next| next < ir.len());
+        match &ir[idx] {
+            IR::Jump(Label::Function(_), _) => next.into_iter().collect(),
+            IR::Jump(label, JumpCond::Always) => labels.get(label).copied().into_iter().collect(),
+            IR::Jump(label, JumpCond::Compare(..)) => labels.get(label).copied().into_iter().chain(next).collect(),
+            IR::End => vec![],
+            IR::Set(dest, _) if dest == "@counter" => vec![],
+            _ => next.into_iter().collect(),
+        }
+    }).collect()
+}
+
+/// The variables passing `tracked` that may still be read after each instruction, before they're written again.
+pub fn live_out(ir: &[IR], successors: &[Vec<usize>], tracked: impl Fn(&str) -> bool) -> Vec<HashSet<String>> {
+    let (defs, uses) = ir.iter().map(|fragment| {
+        let (def, uses) = fragment.def_use();
+        (
+            def.filter(|def| tracked(def)).cloned(),
+            uses.into_iter().filter(|used| tracked(used)).cloned().collect(),
+        )
+    }).unzip::<_, _, Vec<Option<String>>, Vec<Vec<String>>>();
+    liveness(successors, &defs, &uses)
+}
+
+/// The variables that may still be read after each instruction, given what each instruction writes and reads.
+pub fn liveness(successors: &[Vec<usize>], defs: &[Option<String>], uses: &[Vec<String>]) -> Vec<HashSet<String>> {
+    let mut live_out: Vec<HashSet<String>> = vec![HashSet::new(); successors.len()];
+    let mut live_in: Vec<HashSet<String>> = vec![HashSet::new(); successors.len()];
+    // Going backwards, as liveness flows from uses to definitions
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in (0..successors.len()).rev() {
+            let out = successors[idx].iter().flat_map(|&succ| live_in[succ].iter().cloned()).collect::<HashSet<String>>();
+            let mut live = out.clone();
+            if let Some(def) = &defs[idx] {
+                live.remove(def);
+            }
+            live.extend(uses[idx].iter().cloned());
+            if live != live_in[idx] {
+                live_in[idx] = live;
+                changed = true;
+            }
+            live_out[idx] = out;
+        }
+    }
+    live_out
+}
@@ -1,33 +1,103 @@
 use crate::lex::{BinOp, Token, UnOp};
 
 pub fn is_identifier_char(ch: char) -> bool {
-    matches!(ch, 'a'..='z' | 'A'..='Z' | '_')
+    matches!(ch, 'a'..='z' | 'A'..='Z' | '_' | '0'..='9')
 }
 
 /// Tokens whose names overlap the definitions of identifiers need to be checked and overidden.
 pub fn is_keyword(kw: &str) -> Option<Token> {
     match kw {
-        "ang" => Some(Token::BinaryOp(BinOp::Angle)),
-        "max" => Some(Token::BinaryOp(BinOp::Max)),
-        "min" => Some(Token::BinaryOp(BinOp::Min)),
-        "len" => Some(Token::BinaryOp(BinOp::Len)),
-        "noise" => Some(Token::BinaryOp(BinOp::Noise)),
         "if" => Some(Token::If),
         "while" => Some(Token::While),
         "else" => Some(Token::Else),
-        "abs" => Some(Token::UnaryOp(UnOp::Abs)),
-        "ln" => Some(Token::UnaryOp(UnOp::Log)),
-        "log" => Some(Token::UnaryOp(UnOp::Log10)),
-        "floor" => Some(Token::UnaryOp(UnOp::Floor)),
-        "ciel" => Some(Token::UnaryOp(UnOp::Ceil)),
-        "sqrt" => Some(Token::UnaryOp(UnOp::Sqrt)),
-        "rand" => Some(Token::UnaryOp(UnOp::Rand)),
-        "sin" => Some(Token::UnaryOp(UnOp::Sin)),
-        "cos" => Some(Token::UnaryOp(UnOp::Cos)),
-        "tan" => Some(Token::UnaryOp(UnOp::Tan)),
-        "asin" => Some(Token::UnaryOp(UnOp::Asin)),
-        "acos" => Some(Token::UnaryOp(UnOp::Acos)),
-        "atan" => Some(Token::UnaryOp(UnOp::Atan)),
-        _ => None,
+        "break" => Some(Token::Break),
+        "continue" => Some(Token::Continue),
+        "fn" => Some(Token::Fn),
+        "inline" => Some(Token::Inline),
+        "return" => Some(Token::Return),
+        _ => BinOp::from_name(kw).map(Token::BinaryOp).or_else(|| UnOp::from_name(kw).map(Token::UnaryOp)),
     }
 }
+
+/// The `@` builtins Mindustry defines: processor variables, sensable properties, items, liquids, units and common blocks.
+/// There are too many blocks to list them all, so this is only used to catch typos.
+pub const BUILTIN_VARIABLES: &[&str] = &[
+    // Processor variables and constants
+    "@counter", "@this", "@thisx", "@thisy", "@ipt", "@links", "@time", "@tick", "@second", "@minute",
+    "@unit", "@mapw", "@maph", "@waveNumber", "@waveTime", "@server", "@client", "@clientLocale", "@clientUnit",
+    "@clientName", "@clientTeam", "@clientMobile", "@pi", "@e", "@degToRad", "@radToDeg",
+    "@ctrlProcessor", "@ctrlPlayer", "@ctrlCommand", "@air", "@solid", "@blockCount", "@unitCount", "@itemCount", "@liquidCount",
+    // Sensable properties
+    "@totalItems", "@firstItem", "@totalLiquids", "@totalPower", "@itemCapacity", "@liquidCapacity", "@powerCapacity",
+    "@powerNetStored", "@powerNetCapacity", "@powerNetIn", "@powerNetOut", "@ammo", "@ammoCapacity", "@currentAmmoType",
+    "@health", "@maxHealth", "@heat", "@shield", "@armor", "@efficiency", "@progress", "@timescale", "@rotation",
+    "@x", "@y", "@velocityX", "@velocityY", "@shootX", "@shootY", "@cameraX", "@cameraY", "@cameraWidth", "@cameraHeight",
+    "@size", "@dead", "@range", "@shooting", "@boosting", "@mineX", "@mineY", "@mining", "@speed", "@team", "@type",
+    "@flag", "@controlled", "@controller", "@name", "@payloadCount", "@payloadType", "@totalPayload", "@payloadCapacity",
+    "@id", "@enabled", "@shoot", "@shootp", "@config", "@color",
+    // Items
+    "@copper", "@lead", "@metaglass", "@graphite", "@sand", "@coal", "@titanium", "@thorium", "@scrap", "@silicon",
+    "@plastanium", "@phase-fabric", "@surge-alloy", "@spore-pod", "@blast-compound", "@pyratite", "@beryllium",
+    "@tungsten", "@oxide", "@carbide", "@fissile-matter", "@dormant-cyst",
+    // Liquids
+    "@water", "@slag", "@oil", "@cryofluid", "@neoplasm", "@arkycite", "@gallium", "@ozone", "@hydrogen", "@nitrogen", "@cyanogen",
+    // Units
+    "@dagger", "@mace", "@fortress", "@scepter", "@reign", "@nova", "@pulsar", "@quasar", "@vela", "@corvus",
+    "@crawler", "@atrax", "@spiroct", "@arkyid", "@toxopid", "@flare", "@horizon", "@zenith", "@antumbra", "@eclipse",
+    "@mono", "@poly", "@mega", "@quad", "@oct", "@risso", "@minke", "@bryde", "@sei", "@omura",
+    "@retusa", "@oxynoe", "@cyerce", "@aegires", "@navanax", "@alpha", "@beta", "@gamma",
+    "@stell", "@locus", "@precept", "@vanquish", "@conquer", "@merui", "@cleroi", "@anthicus", "@tecta", "@collaris",
+    "@elude", "@avert", "@obviate", "@quell", "@disrupt", "@evoke", "@incite", "@emanate",
+    // Common blocks
+    "@core-shard", "@core-foundation", "@core-nucleus", "@container", "@vault", "@sorter", "@inverted-sorter", "@router",
+    "@conveyor", "@titanium-conveyor", "@junction", "@bridge-conveyor", "@overflow-gate", "@underflow-gate", "@unloader",
+    "@message", "@switch", "@micro-processor", "@logic-processor", "@hyper-processor", "@memory-cell", "@memory-bank",
+    "@logic-display", "@large-logic-display", "@illuminator", "@duo", "@scatter", "@hail", "@lancer", "@wave", "@salvo",
+    "@ripple", "@cyclone", "@fuse", "@spectre", "@meltdown", "@foreshadow", "@mender", "@battery", "@power-node",
+];
+
+/// Whether `name` is a builtin Mindustry defines, as far as `BUILTIN_VARIABLES` knows.
+pub fn is_builtin_variable(name: &str) -> bool {
+    BUILTIN_VARIABLES.contains(&name)
+}
+
+/// Whether `name` looks like the name Mindustry gives a block linked to a processor: the last word of the block's name
+/// followed by a number, such as `cell1`, `message2` or `display3`.
+pub fn is_linked_block(name: &str) -> bool {
+    let base = name.trim_end_matches(|ch: char| ch.is_ascii_digit());
+    base.len() < name.len() && LINK_NAMES.contains(&base)
+}
+
+/// The names Mindustry links blocks as, minus the number.
+pub const LINK_NAMES: &[&str] = &[
+    "cell", "bank", "message", "switch", "display", "canvas", "processor", "sorter", "inverted-sorter", "conveyor",
+    "door", "node", "diode", "battery", "unloader", "vault", "container", "core", "turret", "duo", "hail", "lancer",
+    "wave", "salvo", "ripple", "cyclone", "fuse", "foreshadow", "spectre", "meltdown", "segment", "tsunami", "arc",
+    "parallax", "scatter", "illuminator", "factory", "smelter", "kiln", "drill", "pump", "conduit", "tank", "generator",
+    "reactor", "mender", "projector", "router", "sensor", "gate", "bridge", "launchpad", "silo", "speaker",
+];
+
+/// The known builtin closest to `name` if it's only a couple of letters off, which makes `name` most likely a typo of it.
+pub fn similar_builtin(name: &str) -> Option<&'static str> {
+    BUILTIN_VARIABLES.iter()
+        .map(|builtin| (edit_distance(name, builtin), *builtin))
+        .filter(|&(distance, _)| distance <= 2 && distance * 2 < name.len() - 1)
+        .min()
+        .map(|(_, builtin)| builtin)
+}
+
+/// The number of characters that have to be inserted, removed or replaced to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut row = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
@@ -1,187 +1,297 @@
+use crate::error::{CompileError, Warning};
 use crate::is;
 use crate::next;
+use crate::span::{SourceMap, Span};
 
-pub fn lex(code_chars: &[char]) -> Vec<Token> {
-    let mut tokens: Vec<Token> = vec![];
-    let mut st: String = String::new();
+/// A token along with where it was found in the script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Turns a script into tokens. Lexing continues past bad characters so that every error in the script is reported.
+pub fn lex(code_chars: &[char]) -> Result<Vec<SpannedToken>, Vec<CompileError>> {
+    let map = SourceMap::new(code_chars);
+    let mut tokens: Vec<SpannedToken> = vec![];
+    let mut errors: Vec<CompileError> = vec![];
     let mut i: usize = 0;
-    while i <= code_chars.len() {
-        if let Some(kw) = is::is_keyword(&st) {
-            tokens.push(kw);
-        } else {
-            match match &st[..] {
-                "#" => {
-                    let start = i;
-                    let end = loop {
-                        if let Some('\\') = next::next_char(&mut i, &code_chars) { i += 1; continue; }
-                        if let Some('#') = next::next_char(&mut i, &code_chars) {
-                            break i - 1;
+    while let Some(ch) = next::next_char(&mut i, code_chars) {
+        let start = i - 1;
+        let token = match ch {
+            '#' => {
+                let end = loop {
+                    match next::next_char(&mut i, code_chars) {
+                        Some('\\') => i += 1,
+                        Some('#') => break i - 1,
+                        None => {
+                            errors.push(CompileError::Lex(map.span(start, start + 1), "unterminated `#`, expected a closing `#`".to_string()));
+                            break code_chars.len();
                         }
-                    };
-                    Some(Token::Preproc(st[start..end].to_string()))
-                }
-                "$" => {
-                    let start = i;
-                    let end = loop {
-                        if let Some('\\') = next::next_char(&mut i, &code_chars) { i += 1; continue; }
-                        if let Some('$') = next::next_char(&mut i, &code_chars) {
-                            break i - 1;
+                        _ => {}
+                    }
+                };
+                Token::Preproc(code_chars[start + 1..end].iter().collect::<String>())
+            }
+            '$' => {
+                let end = loop {
+                    match next::next_char(&mut i, code_chars) {
+                        Some('\\') => i += 1,
+                        Some('$') => break i - 1,
+                        None => {
+                            errors.push(CompileError::Lex(map.span(start, start + 1), "unterminated `$`, expected a closing `$`".to_string()));
+                            break code_chars.len();
                         }
-                    };
-                    Some(Token::InlineAsm(code_chars[start..end].iter().collect::<String>()))
+                        _ => {}
+                    }
+                };
+                Token::InlineAsm(code_chars[start + 1..end].iter().collect::<String>())
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match next::next_char(&mut i, code_chars) {
+                        Some('"') => break,
+                        Some('\\') => match next::next_escape(&mut i, code_chars) {
+                            // Mindustry ends a string at the first `"`, there's no way to print one
+                            Ok('"') => errors.push(CompileError::Lex(map.span(i - 2, i), "Mindustry strings can't contain `\"`".to_string())),
+                            Ok(c) => text.push(c),
+                            Err(message) => errors.push(CompileError::Lex(map.span(i - 2, i), message)),
+                        },
+                        Some(c) => text.push(c),
+                        None => {
+                            errors.push(CompileError::Lex(map.span(start, start + 1), "unterminated string, expected a closing `\"`".to_string()));
+                            break;
+                        }
+                    }
                 }
-                "+" => Some(Token::BinaryOp(BinOp::Add)),
-                "-" => Some(Token::BinaryOp(BinOp::Sub)),
-                "*" => Some(Token::BinaryOp(BinOp::Mul)),
-                "/" => {
-                    if let Some('/') = next::next_char(&mut i, &code_chars) {
-                        Some(Token::BinaryOp(BinOp::IDiv))
-                    } else {
+                Token::Str(text)
+            }
+            // A character literal is its code point
+            '\'' => {
+                let ch = match next::next_char(&mut i, code_chars) {
+                    Some('\\') => next::next_escape(&mut i, code_chars),
+                    Some('\'') => {
                         i -= 1;
-                        Some(Token::BinaryOp(BinOp::Div))
+                        Err("expected a character in the character literal".to_string())
                     }
+                    None => Err("expected a character in the character literal".to_string()),
+                    Some(c) => Ok(c),
+                };
+                if code_chars.get(i) == Some(&'\'') {
+                    i += 1;
+                } else {
+                    while !matches!(next::next_char(&mut i, code_chars), Some('\'' | '\n') | None) {}
+                    errors.push(CompileError::Lex(map.span(start, i), "character literals hold a single character, expected a closing `'`".to_string()));
+                    continue;
                 }
-                "%" => Some(Token::BinaryOp(BinOp::Mod)),
-                "&" => {
-                    if let Some('/') = next::next_char(&mut i, &code_chars) {
-                        Some(Token::BinaryOp(BinOp::And))
-                    } else {
+                match ch {
+                    Ok(ch) => Token::Num(ch as u32 as f64),
+                    Err(message) => {
+                        errors.push(CompileError::Lex(map.span(start, i), message));
+                        continue;
+                    }
+                }
+            }
+            '+' => Token::BinaryOp(BinOp::Add),
+            '-' => Token::BinaryOp(BinOp::Sub),
+            '*' => Token::BinaryOp(BinOp::Mul),
+            '/' => {
+                match next::next_char(&mut i, code_chars) {
+                    // Line comment
+                    Some('/') => {
+                        while !matches!(next::next_char(&mut i, code_chars), Some('\n') | None) {}
+                        continue;
+                    }
+                    // Block comment, which can be nested
+                    Some('*') => {
+                        let mut depth = 1;
+                        while depth > 0 {
+                            match next::next_char(&mut i, code_chars) {
+                                Some('/') if code_chars.get(i) == Some(&'*') => {
+                                    i += 1;
+                                    depth += 1;
+                                }
+                                Some('*') if code_chars.get(i) == Some(&'/') => {
+                                    i += 1;
+                                    depth -= 1;
+                                }
+                                Some(_) => {}
+                                None => {
+                                    errors.push(CompileError::Lex(map.span(start, start + 2), "unterminated block comment, expected a closing `*/`".to_string()));
+                                    break;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    _ => {
                         i -= 1;
-                        Some(Token::BinaryOp(BinOp::Band))
+                        Token::BinaryOp(BinOp::Div)
                     }
                 }
-                "|" => Some(Token::BinaryOp(BinOp::Bor)),
-                "^" => {
-                    if let Some('^') = next::next_char(&mut i, &code_chars) {
-                        Some(Token::BinaryOp(BinOp::Pow))
+            }
+            '%' => Token::BinaryOp(BinOp::Mod),
+            '&' => {
+                if let Some('&') = next::next_char(&mut i, code_chars) {
+                    Token::BinaryOp(BinOp::And)
+                } else {
+                    i -= 1;
+                    Token::BinaryOp(BinOp::Band)
+                }
+            }
+            '|' => {
+                if let Some('|') = next::next_char(&mut i, code_chars) {
+                    Token::BinaryOp(BinOp::Or)
+                } else {
+                    i -= 1;
+                    Token::BinaryOp(BinOp::Bor)
+                }
+            }
+            '^' => {
+                if let Some('^') = next::next_char(&mut i, code_chars) {
+                    Token::BinaryOp(BinOp::Pow)
+                } else {
+                    i -= 1;
+                    Token::BinaryOp(BinOp::Bxor)
+                }
+            }
+            '=' => {
+                //==
+                if let Some('=') = next::next_char(&mut i, code_chars) {
+                    //===
+                    if let Some('=') = next::next_char(&mut i, code_chars) {
+                        Token::BinaryOp(BinOp::Streq)
                     } else {
                         i -= 1;
-                        Some(Token::BinaryOp(BinOp::Bxor))
+                        Token::BinaryOp(BinOp::Eq)
                     }
+                //=
+                } else {
+                    i -= 1;
+                    Token::Assignment
                 }
-                "=" => {
-                    //==
-                    if let Some('=') = next::next_char(&mut i, &code_chars) {
-                        //===
-                        if let Some('=') = next::next_char(&mut i, &code_chars) {
-                            Some(Token::BinaryOp(BinOp::Streq))
+            }
+            '>' => {
+                match next::next_char(&mut i, code_chars) {
+                    Some('=') => Token::BinaryOp(BinOp::GreaterE),
+                    Some('>') => {
+                        if let Some('>') = next::next_char(&mut i, code_chars) {
+                            Token::BinaryOp(BinOp::UShr)
                         } else {
                             i -= 1;
-                            Some(Token::BinaryOp(BinOp::Eq))
+                            Token::BinaryOp(BinOp::Rsh)
                         }
-                    //=
-                    } else {
-                        i -= 1;
-                        Some(Token::Assignment)
                     }
-                }
-                ">" => {
-                    let c: Option<char> = next::next_char(&mut i, &code_chars);
-                    if let Some('=') = c {
-                        Some(Token::BinaryOp(BinOp::GreaterE))
-                    } else if let Some('>') = c {
-                        Some(Token::BinaryOp(BinOp::Rsh))
-                    } else {
+                    _ => {
                         i -= 1;
-                        Some(Token::BinaryOp(BinOp::Greater))
+                        Token::BinaryOp(BinOp::Greater)
                     }
                 }
-                "<" => {
-                    let c: Option<char> = next::next_char(&mut i, &code_chars);
-                    if let Some('=') = c {
-                        Some(Token::BinaryOp(BinOp::LessE))
-                    } else if let Some('<') = c {
-                        Some(Token::BinaryOp(BinOp::Lsh))
-                    } else {
+            }
+            '<' => {
+                match next::next_char(&mut i, code_chars) {
+                    Some('=') => Token::BinaryOp(BinOp::LessE),
+                    Some('<') => Token::BinaryOp(BinOp::Lsh),
+                    _ => {
                         i -= 1;
-                        Some(Token::BinaryOp(BinOp::Less))
+                        Token::BinaryOp(BinOp::Less)
                     }
                 }
-                "{" => Some(Token::LBrace),
-                "}" => Some(Token::RBrace),
-                "[" => Some(Token::LBracket),
-                "]" => Some(Token::RBracket),
-                "(" => Some(Token::LParen),
-                ")" => Some(Token::RParen),
-                "," => Some(Token::Comma),
-                ";" => Some(Token::Semicolon),
-                // May be implemented as a special operator (op notEqual a a b)
-                //"!" => Some(Token::UnaryOp(UnOp::Not)),
-                "~" => Some(Token::UnaryOp(UnOp::Flip)),
-                _ => None,
-            } {
-                Some(tok) => {
-                    //dbg!(&tok);
-                    tokens.push(tok);
-                    st = String::new();
+            }
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            '!' => {
+                if let Some('=') = next::next_char(&mut i, code_chars) {
+                    Token::BinaryOp(BinOp::Neq)
+                } else {
+                    i -= 1;
+                    Token::UnaryOp(UnOp::Not)
                 }
-                None => {
-                    if let Some(ch) = next::next_char(&mut i, &code_chars) {
-                        match ch {
-                            '0'..='9' => {
-                                i -= 1;
-                                tokens.push(Token::Num(next::next_number(
-                                    &mut i,
-                                    &code_chars,
-                                    false,
-                                )));
-                            }
-                            '-' => {
-                                while let Some(' ') = next::next_char(&mut i, &code_chars) { }
-                                i -= 1;
-                                if let Some(c) = next::next_char(&mut i, &code_chars) {
-                                    if ('0'..'9').contains(&c) {
-                                        i -= 1;
-                                        tokens.push(Token::Num(next::next_number(
-                                            &mut i,
-                                            &code_chars,
-                                            true,
-                                        )));
-                                    } else {
-                                        tokens.push(Token::BinaryOp(BinOp::Sub));
-                                        i -= 1;
-                                    }
-                                } else {
-                                    tokens.push(Token::BinaryOp(BinOp::Sub));
-                                }
-                            }
-                            'a'..='z' | 'A'..='Z' | '_' => {
-                                i -= 1;
-                                let next_raw_iden = &next::next_identifier(&mut i, &code_chars)[..];
-                                if let Some(kw) = is::is_keyword(next_raw_iden) {
-                                    tokens.push(kw);
-                                } else {
-                                    tokens.push(Token::Identifier(next_raw_iden.to_string()))
-                                }
-                            }
-                            ' ' => st = String::new(),
-                            '\n' | '\r' => {},
-                            c => st.push(c),
-                        }
+            }
+            '~' => Token::UnaryOp(UnOp::Flip),
+            '0'..='9' => {
+                i -= 1;
+                match next::next_number(&mut i, code_chars) {
+                    Ok(n) => Token::Num(n),
+                    Err(message) => {
+                        errors.push(CompileError::Lex(map.span(start, i), message));
+                        continue;
                     }
                 }
             }
-        }
-    }
-    
-    // Fix tokens (example: 20-10 is recognized as `num num`, not `num op num`)
-    let mut t = 0;
-    while t <= tokens.len() {
-        match next::next_token(&mut t, &tokens) {
-            Some(Token::Num(_) | Token::Identifier(_)) => {
-                if let Some(Token::Num(n)) = next::next_token(&mut t, &tokens) {
-                    if n.is_sign_negative() {
-                        tokens.insert(t - 1, Token::BinaryOp(BinOp::Sub));
-                        tokens[t] = Token::Num(n.abs());
-                    }
+            '@' => {
+                i -= 1;
+                let name = next::next_builtin(&mut i, code_chars);
+                if name == "@" {
+                    errors.push(CompileError::Lex(map.span(start, i), "expected the name of a builtin after `@`".to_string()));
+                    continue;
                 }
+                Token::Builtin(name)
             }
-            None => break,
-            _ => {}
+            'a'..='z' | 'A'..='Z' | '_' => {
+                i -= 1;
+                let next_raw_iden = next::next_identifier(&mut i, code_chars);
+                is::is_keyword(&next_raw_iden).unwrap_or(Token::Identifier(next_raw_iden))
+            }
+            ' ' | '\t' | '\n' | '\r' => continue,
+            c => {
+                errors.push(CompileError::Lex(map.span(start, i), format!("unexpected character `{c}`")));
+                continue;
+            }
+        };
+        tokens.push(SpannedToken { token, span: map.span(start, i) });
+    }
+
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Warns about every `@` builtin that isn't known to Mindustry but is close to one that is, as it's most likely a typo.
+pub fn check_builtins(tokens: &[SpannedToken]) -> Vec<Warning> {
+    tokens.iter().filter_map(|SpannedToken { token, span }| {
+        let Token::Builtin(name) = token else { return None };
+        if is::is_builtin_variable(name) {
+            return None;
         }
+        let similar = is::similar_builtin(name)?;
+        Some(Warning::new(*span, format!("unknown builtin `{name}`, did you mean `{similar}`?")))
+    }).collect()
+}
+
+/// Warns about each variable in `removed`, pointing at the first place it appears in the script.
+pub fn removed_variables(tokens: &[SpannedToken], removed: &[String]) -> Vec<Warning> {
+    let mut warnings = removed.iter().filter_map(|name| {
+        let first = tokens.iter().find(|SpannedToken { token, .. }| matches!(token, Token::Identifier(ident) if ident == name))?;
+        Some(Warning::new(first.span, format!("variable `{name}` doesn't affect the program, so it was removed")))
+    }).collect::<Vec<Warning>>();
+    warnings.sort_by_key(|warning| warning.span);
+    warnings
+}
+
+/// Reports every identifier starting with `prefix`, which is reserved for the compiler's temporary variables.
+pub fn check_reserved(tokens: &[SpannedToken], prefix: &str) -> Result<(), Vec<CompileError>> {
+    let errors = tokens.iter().filter_map(|SpannedToken { token, span }| match token {
+        Token::Identifier(ident) if ident.starts_with(prefix) => Some(CompileError::Semantic(
+            *span,
+            format!("`{ident}` starts with `{prefix}`, which is reserved for temporary variables"),
+        )),
+        _ => None,
+    }).collect::<Vec<CompileError>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
     }
-    
-    tokens
 }
 
 use std::fmt::{Display, Formatter};
@@ -201,6 +311,7 @@ pub enum BinOp {
 
     Lsh,
     Rsh,
+    UShr,
 
     Less,
     LessE,
@@ -219,52 +330,166 @@ pub enum BinOp {
 
     And,
 
+    Or,
+
     Max,
     Min,
     Angle,
+    AngleDiff,
     Len,
     Noise,
+    LogN,
+    EMod,
 }
+
+/// Mindustry's 1 or 0 for true or false.
+fn truth(b: bool) -> f64 {
+    if b { 1.0 } else { 0.0 }
+}
+
+/// Mindustry stores results that aren't a finite number, like dividing by zero, as 0.
+fn valid(n: f64) -> f64 {
+    if n.is_finite() { n } else { 0.0 }
+}
+
+/// Which side a chain of operators with the same precedence groups from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a ^^ b ^^ c` is `a ^^ (b ^^ c)`
+    Right,
+}
+
+/// Every operation taking two operands, with its name in Mindustry logic, the names it can be called by in scripts,
+/// its precedence (higher binds tighter) and which way it groups. Operators without names are written with symbols, see `lex`.
+/// To support a new `op`, add a variant and a row here.
+const BINARY_OPS: &[(BinOp, &str, &[&str], u8, Assoc)] = &[
+    (BinOp::Pow, "pow", &[], 12, Assoc::Right),
+    (BinOp::Mul, "mul", &[], 11, Assoc::Left),
+    (BinOp::Div, "div", &[], 11, Assoc::Left),
+    (BinOp::IDiv, "idiv", &["idiv"], 11, Assoc::Left),
+    (BinOp::Mod, "mod", &[], 11, Assoc::Left),
+    (BinOp::Add, "add", &[], 10, Assoc::Left),
+    (BinOp::Sub, "sub", &[], 10, Assoc::Left),
+    (BinOp::Lsh, "shl", &[], 9, Assoc::Left),
+    (BinOp::Rsh, "shr", &[], 9, Assoc::Left),
+    (BinOp::UShr, "ushr", &[], 9, Assoc::Left),
+    (BinOp::Less, "lessThan", &[], 8, Assoc::Left),
+    (BinOp::LessE, "lessThanEq", &[], 8, Assoc::Left),
+    (BinOp::Greater, "greaterThan", &[], 8, Assoc::Left),
+    (BinOp::GreaterE, "greaterThanEq", &[], 8, Assoc::Left),
+    (BinOp::Eq, "equal", &[], 7, Assoc::Left),
+    (BinOp::Neq, "notEqual", &[], 7, Assoc::Left),
+    (BinOp::Streq, "strictEqual", &[], 7, Assoc::Left),
+    (BinOp::Band, "and", &[], 6, Assoc::Left),
+    (BinOp::Bxor, "xor", &[], 5, Assoc::Left),
+    (BinOp::Bor, "or", &[], 4, Assoc::Left),
+    (BinOp::And, "land", &[], 3, Assoc::Left),
+    // Mindustry has no logical or, so `a || b` is parsed into `(a != 0) | (b != 0)` and this never reaches codegen
+    (BinOp::Or, "or", &[], 2, Assoc::Left),
+    (BinOp::Max, "max", &["max"], 1, Assoc::Left),
+    (BinOp::Min, "min", &["min"], 1, Assoc::Left),
+    (BinOp::Angle, "angle", &["angle", "ang"], 1, Assoc::Left),
+    (BinOp::AngleDiff, "angleDiff", &["angleDiff"], 1, Assoc::Left),
+    (BinOp::Len, "len", &["len"], 1, Assoc::Left),
+    (BinOp::Noise, "noise", &["noise"], 1, Assoc::Left),
+    (BinOp::LogN, "logn", &["logn"], 1, Assoc::Left),
+    (BinOp::EMod, "emod", &["emod"], 1, Assoc::Left),
+];
+
 impl BinOp {
+    fn row(&self) -> &'static (BinOp, &'static str, &'static [&'static str], u8, Assoc) {
+        BINARY_OPS.iter().find(|row| row.0 == *self).expect("every binary operator has a row in `BINARY_OPS`")
+    }
+    /// The operator called `name` in scripts, such as `max`.
+    pub fn from_name(name: &str) -> Option<BinOp> {
+        BINARY_OPS.iter().find(|row| row.2.contains(&name)).map(|row| row.0)
+    }
+    /// How tightly this operator binds, higher binding tighter, and how it groups.
+    /// Unary operators bind tighter than all of these.
+    pub fn precedence(&self) -> (u8, Assoc) {
+        let row = self.row();
+        (row.3, row.4)
+    }
     pub fn code(&self) -> &'static str {
+        self.row().1
+    }
+    /// Computes the operation on two numbers the way Mindustry does, or `None` if the result can't be known ahead of time
+    /// (`noise`), or Mindustry computes it with single precision or library functions whose exact results can't be matched.
+    pub fn eval(&self, a: f64, b: f64) -> Option<f64> {
+        let result = match self {
+            BinOp::Add => a + b,
+            BinOp::Sub => a - b,
+            BinOp::Mul => a * b,
+            BinOp::Div => a / b,
+            BinOp::IDiv => (a / b).floor(),
+            BinOp::Mod => a % b,
+            BinOp::EMod => ((a % b) + b) % b,
+            // Java's `pow` may be off by a bit, except for whole powers of whole numbers small enough to hold every digit
+            BinOp::Pow if a.fract() == 0.0 && b.fract() == 0.0 && b >= 0.0 && a.powf(b).abs() <= (1u64 << 53) as f64 => a.powf(b),
+            BinOp::Pow => return None,
+            // Bitwise operations work on 64 bit integers, and shifts only use the lowest 6 bits of the amount like Java
+            BinOp::Lsh => (a as i64).wrapping_shl(b as i64 as u32) as f64,
+            BinOp::Rsh => (a as i64).wrapping_shr(b as i64 as u32) as f64,
+            BinOp::UShr => ((a as i64 as u64).wrapping_shr(b as i64 as u32)) as i64 as f64,
+            BinOp::Band => ((a as i64) & (b as i64)) as f64,
+            BinOp::Bxor => ((a as i64) ^ (b as i64)) as f64,
+            BinOp::Bor => ((a as i64) | (b as i64)) as f64,
+            BinOp::Less => truth(a < b),
+            BinOp::LessE => truth(a <= b),
+            BinOp::Greater => truth(a > b),
+            BinOp::GreaterE => truth(a >= b),
+            // Mindustry treats numbers this close as equal
+            BinOp::Eq => truth((a - b).abs() < 0.000001),
+            BinOp::Neq => truth((a - b).abs() >= 0.000001),
+            BinOp::Streq => truth(a == b),
+            BinOp::And => truth(a != 0.0 && b != 0.0),
+            BinOp::Or => truth(a != 0.0 || b != 0.0),
+            BinOp::Max => a.max(b),
+            BinOp::Min => a.min(b),
+            BinOp::Angle | BinOp::AngleDiff | BinOp::Len | BinOp::Noise | BinOp::LogN => return None,
+        };
+        Some(valid(result))
+    }
+    /// Whether this operator is named rather than a symbol. These can also be called like functions, as in `max(a, b)`.
+    pub fn is_builtin(&self) -> bool {
+        !self.row().2.is_empty()
+    }
+    /// The comparison that is true exactly when this one is false, used to jump past blocks.
+    /// `Streq` has no inverse as Mindustry has no strict inequality.
+    pub fn inverse(&self) -> Option<BinOp> {
         match self {
-            BinOp::Pow => "pow",
-            BinOp::Mul => "mul",
-            BinOp::Div => "div",
-            BinOp::IDiv => "idiv",
-            BinOp::Mod => "mod",
-            BinOp::Add => "add",
-            BinOp::Sub => "sub",
-            BinOp::Lsh => "shl",
-            BinOp::Rsh => "shr",
-            BinOp::Less => "lessThan",
-            BinOp::LessE => "lessThenEq",
-            BinOp::Greater => "greaterThan",
-            BinOp::GreaterE => "greaterThanEq",
-            BinOp::Eq => "equal",
-            BinOp::Neq => "notEqual",
-            BinOp::Streq => "strictEqual",
-            BinOp::Band => "and",
-            BinOp::Bxor => "xor",
-            BinOp::Bor => "or",
-            BinOp::And => "land",
-            BinOp::Max => "max",
-            BinOp::Min => "min",
-            BinOp::Angle => "angle",
-            BinOp::Len => "len",
-            BinOp::Noise => "noise",
+            BinOp::Less => Some(BinOp::GreaterE),
+            BinOp::LessE => Some(BinOp::Greater),
+            BinOp::Greater => Some(BinOp::LessE),
+            BinOp::GreaterE => Some(BinOp::Less),
+            BinOp::Eq => Some(BinOp::Neq),
+            BinOp::Neq => Some(BinOp::Eq),
+            _ => None,
         }
     }
+    /// Whether this operation can be used directly as a `jump` condition.
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, BinOp::Less | BinOp::LessE | BinOp::Greater | BinOp::GreaterE | BinOp::Eq | BinOp::Neq | BinOp::Streq)
+    }
+    /// Whether swapping the operands gives the same result.
+    pub fn is_commutative(&self) -> bool {
+        matches!(self, BinOp::Mul | BinOp::Add | BinOp::Eq | BinOp::Neq | BinOp::Streq | BinOp::Band | BinOp::Bxor | BinOp::Bor
+            | BinOp::And | BinOp::Max | BinOp::Min | BinOp::Len)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum UnOp {
     Flip,
     Abs,
+    Sign,
     Log,
     Log10,
     Floor,
     Ceil,
+    Round,
     Sqrt,
     Rand,
     Sin,
@@ -273,31 +498,69 @@ pub enum UnOp {
     Asin,
     Acos,
     Atan,
+
+    /// Unary minus. Mindustry has no negation, so this is emitted as `op sub x 0 a`.
+    Neg,
+    /// Logical not. Emitted as `op equal x a 0`.
+    Not,
 }
+
+/// Every operation taking one operand, with its name in Mindustry logic and the names it can be called by in scripts.
+/// To support a new `op`, add a variant and a row here.
+const UNARY_OPS: &[(UnOp, &str, &[&str])] = &[
+    (UnOp::Flip, "not", &[]),
+    (UnOp::Abs, "abs", &["abs"]),
+    (UnOp::Sign, "sign", &["sign"]),
+    (UnOp::Log, "log", &["ln"]),
+    (UnOp::Log10, "log10", &["log", "log10"]),
+    (UnOp::Floor, "floor", &["floor"]),
+    (UnOp::Ceil, "ceil", &["ceil", "ciel"]),
+    (UnOp::Round, "round", &["round"]),
+    (UnOp::Sqrt, "sqrt", &["sqrt"]),
+    (UnOp::Rand, "rand", &["rand"]),
+    (UnOp::Sin, "sin", &["sin"]),
+    (UnOp::Cos, "cos", &["cos"]),
+    (UnOp::Tan, "tan", &["tan"]),
+    (UnOp::Asin, "asin", &["asin"]),
+    (UnOp::Acos, "acos", &["acos"]),
+    (UnOp::Atan, "atan", &["atan"]),
+    (UnOp::Neg, "sub", &[]),
+    (UnOp::Not, "equal", &[]),
+];
+
 impl UnOp {
+    /// Computes the operation on a number the way Mindustry does, like `BinOp::eval`. `rand` is never known ahead of time.
+    pub fn eval(&self, a: f64) -> Option<f64> {
+        let result = match self {
+            UnOp::Flip => !(a as i64) as f64,
+            UnOp::Abs => a.abs(),
+            // Unlike Rust's `signum`, Java's is 0 for 0
+            UnOp::Sign => if a == 0.0 { 0.0 } else { a.signum() },
+            UnOp::Floor => a.floor(),
+            UnOp::Ceil => a.ceil(),
+            // Java rounds halves up, where Rust rounds them away from zero
+            UnOp::Round => if a - a.floor() >= 0.5 { a.floor() + 1.0 } else { a.floor() },
+            UnOp::Sqrt => a.sqrt(),
+            UnOp::Neg => 0.0 - a,
+            UnOp::Not => truth(a.abs() < 0.000001),
+            UnOp::Log | UnOp::Log10 | UnOp::Rand | UnOp::Sin | UnOp::Cos | UnOp::Tan | UnOp::Asin | UnOp::Acos | UnOp::Atan => return None,
+        };
+        Some(valid(result))
+    }
+    /// The operator called `name` in scripts, such as `sqrt`.
+    pub fn from_name(name: &str) -> Option<UnOp> {
+        UNARY_OPS.iter().find(|row| row.2.contains(&name)).map(|row| row.0)
+    }
     pub fn code(&self) -> &'static str {
-        match self {
-            UnOp::Flip => "not",
-            UnOp::Abs => "abs",
-            UnOp::Log => "log",
-            UnOp::Log10 => "log10",
-            UnOp::Floor => "floor",
-            UnOp::Ceil => "ceil",
-            UnOp::Sqrt => "sqrt",
-            UnOp::Rand => "rand",
-            UnOp::Sin => "sin",
-            UnOp::Cos => "cos",
-            UnOp::Tan => "tan",
-            UnOp::Asin => "asin",
-            UnOp::Acos => "acos",
-            UnOp::Atan => "atan",
-        }
+        UNARY_OPS.iter().find(|row| row.0 == *self).expect("every unary operator has a row in `UNARY_OPS`").1
     }
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Token {
     Num(f64),
+    /// A string literal, with its escapes already replaced.
+    Str(String),
     BinaryOp(BinOp),
     UnaryOp(UnOp),
     LBrace,
@@ -308,6 +571,8 @@ pub enum Token {
     RParen,
     Comma,
     Identifier(String),
+    /// A variable Mindustry defines, such as `@counter` or `@copper`. These can be read but not assigned.
+    Builtin(String),
     Semicolon,
     Assignment,
 
@@ -317,13 +582,20 @@ pub enum Token {
     If,
     While,
     Else,
+    Break,
+    Continue,
+    Fn,
+    Inline,
+    Return,
 }
 impl Token {
+    #[allow(dead_code)]
     pub fn icon(&self) -> &'static str {
         match self {
             Token::InlineAsm(_) => "$",
             Token::Preproc(_) => "P",
             Token::Num(_) => "#",
+            Token::Str(_) => "\"",
             Token::BinaryOp(_) => "B",
             Token::UnaryOp(_) => "U",
             Token::LBrace => "{",
@@ -334,11 +606,17 @@ impl Token {
             Token::RParen => ")",
             Token::Comma => ",",
             Token::Identifier(_) => "N",
+            Token::Builtin(_) => "@",
             Token::Semicolon => ";",
             Token::Assignment => "=",
             Token::If => "I",
             Token::While => "W",
             Token::Else => "E",
+            Token::Break => "K",
+            Token::Continue => "C",
+            Token::Fn => "F",
+            Token::Inline => "L",
+            Token::Return => "R",
         }
     }
 }
@@ -1,74 +1,857 @@
 //! Main file for MindustC. This will take in a MindustC script as an argument and output Mindustry Logic Processor logic.
 //! Modules:
-//!     types: Contains types like Token and BinOp.
-//!     tokenize: Contains the code that turns a script into a list of tokens.
+//!     span: Contains `Span`, the source location attached to tokens, expressions and statements.
+//!     error: Contains `CompileError`, which every phase returns, and its rustc-style rendering.
+//!     lex: Contains types like Token and BinOp, and the code that turns a script into a list of tokens.
 //!     is: Contains functions for checking script segments such as `is_keyword` and `is_iden_char`. Some return an Option, while some return a bool.
 //!     next: Contains functions for stepping through the script such as `next_char` and `next_number`.
+//!     parse: Contains the AST types (`Program`, `Function`, `Statement`, `Expression`) and the IR.
+//!     stmt: Contains the statement parser and the code that generates statement IR, including control flow.
+//!     flow: Contains the control flow graph and liveness analysis the optimizations and the register allocator share.
+//!     opt: Contains the optimizations on the IR: the peephole pass, dead store elimination and value numbering.
+//!     alloc: Contains the register allocator, which names temporaries and reuses them once they're no longer needed.
+//!     budget: Contains the check that the program fits in a processor, with a breakdown of where its instructions go.
+//!     cost: Contains the `--cost` report, estimating how many ticks the program takes on each processor.
 //!
 
-#![feature(iter_intersperse)]
-
 use std::{
-    collections::HashMap, env::{args, Args}, error::Error, fs::File, io::{BufWriter, Read, Write}
+    env::{args, Args}, fs::File, io::{BufWriter, Read, Write}, process::ExitCode
 };
 
-use lex::{Token, lex};
-use parse::expr::{Expression, VarStorage, IR};
+use error::{CompileError, Warning};
+use lex::lex;
+use parse::expr::IR;
 
+mod alloc;
+mod budget;
+mod cost;
+mod error;
+mod flow;
 mod is;
 mod next;
 mod lex;
+mod opt;
 mod parse;
+mod span;
 mod stmt;
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// Settings given on the command line.
+struct Options {
+    /// The memory cell or bank to keep the call stack in, which enables recursion.
+    stack: Option<String>,
+    /// What every temporary variable starts with. Variables in the script can't start with it.
+    register_prefix: String,
+    /// Keeps assignments to variables that are never read, so they can still be watched in the processor.
+    keep_unused: bool,
+    /// The most instructions the program can have.
+    max_instructions: usize,
+    /// Prints an estimate of how long the program takes to run.
+    cost: bool,
+}
+impl Default for Options {
+    fn default() -> Options {
+        Options { stack: None, register_prefix: "__".to_string(), keep_unused: false, max_instructions: budget::DEFAULT_LIMIT, cost: false }
+    }
+}
+
+/// What compiling a script produces.
+#[derive(Debug)]
+struct Compiled {
+    /// The generated logic.
+    code: String,
+    /// The `--cost` report, if it was asked for.
+    cost: Option<String>,
+}
+
+/// Runs every phase over a script, returning the generated logic or every error from the first phase that failed.
+/// Warnings are added to `warnings` whether or not compilation succeeds.
+fn compile(code: &str, options: &Options, warnings: &mut Vec<Warning>) -> Result<Compiled, Vec<CompileError>> {
+    let code_chars = code.chars().collect::<Vec<char>>();
+
+    let tokens = lex(&code_chars)?;
+    warnings.extend(lex::check_builtins(&tokens));
+    lex::check_reserved(&tokens, &options.register_prefix)?;
+
+    let mut program = stmt::parse_program(&tokens)?;
+    stmt::check_program(&program, options.stack.is_some())?;
+    stmt::fold_program(&mut program);
+
+    let mut ctx = stmt::Context::default();
+    ctx.stack = options.stack.clone();
+    let ir = stmt::generate_program(&program, &mut ctx)?;
+    let ir = if options.keep_unused {
+        ir
+    } else {
+        let (ir, removed) = opt::eliminate_dead_stores(ir);
+        warnings.extend(lex::removed_variables(&tokens, &removed));
+        ir
+    };
+    let ir = opt::number_values(ir);
+    let ir = opt::peephole(ir);
+    let ir = alloc::allocate_registers(ir, &options.register_prefix);
+    budget::check_size(&ir, options.max_instructions)?;
+    let cost = options.cost.then(|| cost::report(&ir));
+    let ir = IR::resolve_labels(ir).map_err(|e| vec![e])?;
+    let mut output = IR::make_code(&ir);
+    output.push('\n');
+    Ok(Compiled { code: output, cost })
+}
+
+fn main() -> ExitCode {
     let mut argv: Args = args();
     argv.next();
 
-    let fname = argv.next().expect("Enter a file name to parse!");
+    let mut options = Options::default();
+    let mut positional = vec![];
+    while let Some(arg) = argv.next() {
+        match &arg[..] {
+            "--stack" => match argv.next() {
+                Some(block) => options.stack = Some(block),
+                None => {
+                    eprintln!("error: `--stack` needs the name of a memory cell or bank, such as `cell1`");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--register-prefix" => match argv.next() {
+                Some(prefix) if !prefix.is_empty() => options.register_prefix = prefix,
+                _ => {
+                    eprintln!("error: `--register-prefix` needs the text temporary variables start with, such as `__`");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--keep-unused" => options.keep_unused = true,
+            "--cost" => options.cost = true,
+            "--max-instructions" => match argv.next().and_then(|limit| limit.parse().ok()) {
+                Some(limit) => options.max_instructions = limit,
+                None => {
+                    eprintln!("error: `--max-instructions` needs the most instructions the processor holds, such as `1000`");
+                    return ExitCode::FAILURE;
+                }
+            },
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+
+    let Some(fname) = positional.next() else {
+        eprintln!("error: enter a file name to compile");
+        return ExitCode::FAILURE;
+    };
     let mut code = String::new();
-    File::open(&fname)
-        .expect("File doesn't exist!")
-        .read_to_string(&mut code)?;
+    if let Err(e) = File::open(&fname).and_then(|mut f| f.read_to_string(&mut code)) {
+        eprintln!("error: unable to read `{fname}`: {e}");
+        return ExitCode::FAILURE;
+    }
 
-    let mut out = BufWriter::new(File::create(
-        argv.next().unwrap_or_else(||format!("out.msm"))
-    )?);
+    let mut warnings = vec![];
+    let result = compile(&code, &options, &mut warnings);
+    for w in &warnings {
+        eprintln!("{}", w.render(&fname, &code));
+    }
+    let Compiled { code: output, cost } = match result {
+        Ok(compiled) => compiled,
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("{}", e.render(&fname, &code));
+            }
+            eprintln!("error: could not compile `{fname}` due to {} previous error{}", errors.len(), if errors.len() == 1 { "" } else { "s" });
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let code_chars = code.chars().collect::<Vec<char>>();
+    let out_name = positional.next().unwrap_or_else(|| "out.msm".to_string());
+    if let Err(e) = File::create(&out_name).and_then(|f| BufWriter::new(f).write_all(output.as_bytes())) {
+        eprintln!("error: unable to write `{out_name}`: {e}");
+        return ExitCode::FAILURE;
+    }
+    if let Some(cost) = cost {
+        print!("{cost}");
+    }
+    ExitCode::SUCCESS
+}
 
-    let tokens = lex(&code_chars);
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
 
-    let mut function_table = HashMap::new();
-    function_table.insert("sq".to_string(), 0);
+    use super::{compile, Options};
+    use crate::error::CompileError;
+    use crate::lex::lex;
+    use crate::span::Span;
+    use crate::stmt;
 
-    for i in tokens.split(|t| t == &Token::Semicolon) {
-        match i {
-            [Token::Identifier(ident), Token::Assignment, expr @ ..] => {
-                out.write(IR::make_code(&Expression::make_ast(expr).unwrap().generate_ir(&VarStorage::Identifier(ident.clone())), &function_table).as_bytes())?;
-                out.write(&[b'\n'])?;
+    /// Compiles `code` and runs the logic until it ends or restarts, returning every variable.
+    /// Only the instructions code generation emits are supported.
+    fn run(code: &str) -> HashMap<String, f64> {
+        let options = Options { stack: Some("cell1".to_string()), keep_unused: true, ..Options::default() };
+        let output = compile(code, &options, &mut vec![]).unwrap_or_else(|e| panic!("failed to compile `{code}`: {e:?}")).code;
+        let lines = output.lines().map(|line| line.split(' ').collect::<Vec<&str>>()).collect::<Vec<_>>();
+        let mut vars: HashMap<String, f64> = HashMap::new();
+        let mut memory: HashMap<usize, f64> = HashMap::new();
+        let value = |vars: &HashMap<String, f64>, operand: &str| operand.parse().unwrap_or_else(|_| vars.get(operand).copied().unwrap_or(0.0));
+        let mut counter = 0;
+        for _ in 0..10_000 {
+            let Some(line) = lines.get(counter) else { break };
+            counter += 1;
+            vars.insert("@counter".to_string(), counter as f64);
+            match line[..] {
+                ["set", "@counter", src] => counter = value(&vars, src) as usize,
+                ["set", dest, src] => { vars.insert(dest.to_string(), value(&vars, src)); }
+                ["op", op, dest, a, b] => {
+                    let (a, b) = (value(&vars, a), value(&vars, b));
+                    let result = match op {
+                        "add" => a + b,
+                        "sub" => a - b,
+                        "mul" => a * b,
+                        "div" => a / b,
+                        "idiv" => (a / b).floor(),
+                        "mod" => a % b,
+                        "pow" => a.powf(b),
+                        "max" => a.max(b),
+                        "min" => a.min(b),
+                        "abs" => a.abs(),
+                        "equal" => f64::from(u8::from(a == b)),
+                        "notEqual" => f64::from(u8::from(a != b)),
+                        "lessThan" => f64::from(u8::from(a < b)),
+                        "lessThanEq" => f64::from(u8::from(a <= b)),
+                        "greaterThan" => f64::from(u8::from(a > b)),
+                        "greaterThanEq" => f64::from(u8::from(a >= b)),
+                        "land" => f64::from(u8::from(a != 0.0 && b != 0.0)),
+                        "shl" => ((a as i64) << (b as i64)) as f64,
+                        "and" => ((a as i64) & (b as i64)) as f64,
+                        "xor" => ((a as i64) ^ (b as i64)) as f64,
+                        "or" => ((a as i64) | (b as i64)) as f64,
+                        op => panic!("unsupported op `{op}`"),
+                    };
+                    vars.insert(dest.to_string(), result);
+                }
+                ["jump", target, cond, ref operands @ ..] => {
+                    let taken = match (cond, operands) {
+                        ("always", _) => true,
+                        ("equal", [a, b]) => value(&vars, a) == value(&vars, b),
+                        ("notEqual", [a, b]) => value(&vars, a) != value(&vars, b),
+                        ("lessThan", [a, b]) => value(&vars, a) < value(&vars, b),
+                        ("lessThanEq", [a, b]) => value(&vars, a) <= value(&vars, b),
+                        ("greaterThan", [a, b]) => value(&vars, a) > value(&vars, b),
+                        ("greaterThanEq", [a, b]) => value(&vars, a) >= value(&vars, b),
+                        _ => panic!("unsupported jump `{}`", line.join(" ")),
+                    };
+                    if taken {
+                        let target: usize = target.parse().unwrap();
+                        // Jumping back to the start restarts the program, which is where a run ends
+                        if target == 0 {
+                            break;
+                        }
+                        counter = target;
+                    }
+                }
+                ["write", value_name, _, address] => { memory.insert(value(&vars, address) as usize, value(&vars, value_name)); }
+                ["read", dest, _, address] => { vars.insert(dest.to_string(), memory[&(value(&vars, address) as usize)]); }
+                ["end"] => break,
+                _ => panic!("unsupported instruction `{}`", line.join(" ")),
             }
-            [Token::InlineAsm(logic)] => {
-                out.write(logic.as_bytes())?;
-                out.write(&[b'\n'])?;
-            }
-            _ => {}
         }
+        vars
+    }
+
+    /// Runs `code` and checks the value of `var` afterwards.
+    fn assert_var(code: &str, var: &str, expected: f64) {
+        let vars = run(code);
+        assert_eq!(vars.get(var).copied(), Some(expected), "`{var}` after running `{code}`");
+    }
+
+    /// Compiles `code` with the default options, panicking if it fails.
+    fn compile_ok(code: &str) -> String {
+        let options = Options { keep_unused: true, ..Options::default() };
+        compile(code, &options, &mut vec![]).unwrap_or_else(|e| panic!("failed to compile `{code}`: {e:?}")).code
+    }
+
+    /// Compiles `code`, which has to fail, returning the errors.
+    fn compile_err(code: &str) -> Vec<CompileError> {
+        compile(code, &Options::default(), &mut vec![]).expect_err(&format!("`{code}` compiled"))
+    }
+
+    /// The span of every token in `code`.
+    fn token_spans(code: &str) -> Vec<Span> {
+        lex(&code.chars().collect::<Vec<char>>()).unwrap().into_iter().map(|token| token.span).collect()
+    }
+
+    #[test]
+    fn tokens_know_where_they_are() {
+        let spans = token_spans("x = 1;\n  yy = x + 20;");
+        assert_eq!(spans[4], Span { start: 9, end: 11, line: 2, col: 3 });
+        assert_eq!(spans[8], Span { start: 18, end: 20, line: 2, col: 12 });
+        // Offsets are in bytes, while columns count characters
+        let spans = token_spans("$é$ x = 1;");
+        assert_eq!(spans[1], Span { start: 5, end: 6, line: 1, col: 5 });
+    }
+
+    #[test]
+    fn statements_span_their_tokens() {
+        let code = "x = 1;\n  yy = x + 20;";
+        let tokens = lex(&code.chars().collect::<Vec<char>>()).unwrap();
+        let statements = stmt::parse_program(&tokens).unwrap().statements;
+        let spans = statements.iter().map(|statement| &code[statement.span.start..statement.span.end]).collect::<Vec<&str>>();
+        assert_eq!(spans, ["x = 1;", "yy = x + 20;"]);
+        assert_eq!((statements[1].span.line, statements[1].span.col), (2, 3));
+    }
+
+    #[test]
+    fn errors_point_at_their_source() {
+        let code = "x = 1;\n  y = 3 +;\n";
+        let errors = compile_err(code);
+        assert_eq!(errors.len(), 1);
+        let span = errors[0].span();
+        assert_eq!((span.line, span.col, &code[span.start..span.end]), (2, 9, "+"));
+        assert_eq!(errors[0].render("a.mc", code), "\
+error[parse]: operator `Add` is missing an operand
+ --> a.mc:2:9
+  |
+2 |   y = 3 +;
+  |         ^
+");
+
+        // Every error is reported, not just the first
+        let code = "x = 1;\nfoo(1);\nbar(2);";
+        let errors = compile_err(code);
+        let found = errors.iter().map(|e| (e.span().line, e.message())).collect::<Vec<_>>();
+        assert_eq!(found, [(2, "cannot find function `foo`"), (3, "cannot find function `bar`")]);
+        assert!(errors[1].render("a.mc", code).contains("3 | bar(2);\n  | ^^^^^^\n"), "{}", errors[1].render("a.mc", code));
+    }
+
+    #[test]
+    fn unterminated_delimiters() {
+        for (code, message, col) in [
+            ("x = 1;\ny = $set a 1;", "unterminated `$`, expected a closing `$`", 5),
+            ("a = #oops", "unterminated `#`, expected a closing `#`", 5),
+            ("x = \"text;", "unterminated string, expected a closing `\"`", 5),
+            ("x = 1; /* /* */", "unterminated block comment, expected a closing `*/`", 8),
+        ] {
+            let errors = compile_err(code);
+            assert_eq!(errors.len(), 1, "{code}");
+            assert_eq!(errors[0].message(), message, "{code}");
+            assert_eq!(errors[0].span().col, col, "{code}");
+            assert!(errors[0].render("a.mc", code).contains(&format!("\n  | {}^", " ".repeat(col - 1))), "{code}");
+        }
+    }
+
+    #[test]
+    fn if_else_lowering() {
+        let code = compile_ok("if (a < 3) { x = 1; } else if (a == 5) { x = 2; } else { x = 3; } y = x;");
+        assert_eq!(code, "\
+jump 3 greaterThanEq a 3
+set x 1
+jump 7 always
+jump 6 notEqual a 5
+set x 2
+jump 7 always
+set x 3
+set y x
+");
+        assert_eq!(compile_ok("if (a) { x = 1; } y = x;"), "jump 2 equal a 0\nset x 1\nset y x\n");
+        let code = "if (a < 3) { x = 1; } else if (a == 5) { x = 2; } else { x = 3; }";
+        for (a, x) in [(1.0, 1.0), (5.0, 2.0), (4.0, 3.0)] {
+            assert_var(&format!("a = {a}; {code}"), "x", x);
+        }
+        assert_var("a = 2; x = 0; if (a * 2 > 3) { if (a == 1) { x = 1; } else { x = 2; } }", "x", 2.0);
+    }
+
+    #[test]
+    fn while_with_break_and_continue() {
+        let code = "i = 0; s = 0; while (i < 10) { i = i + 1; if (i == 3) { continue; } if (i > 6) { break; } s = s + i; }";
+        assert_var(code, "s", 1.0 + 2.0 + 4.0 + 5.0 + 6.0);
+        assert_var(code, "i", 7.0);
+        let code = compile_ok(&format!("{code} y = s;"));
+        let lines = code.lines().collect::<Vec<&str>>();
+        // The condition is checked before the first iteration and then at the bottom of the loop
+        assert_eq!(lines[2], "jump 10 greaterThanEq i 10", "{code}");
+        assert_eq!(lines[9], "jump 3 lessThan i 10", "{code}");
+        // `continue` goes to the check at the bottom and `break` past the loop
+        assert_eq!(lines[5], "jump 9 always", "{code}");
+        assert_eq!(lines[7], "jump 10 always", "{code}");
+        assert_var("i = 0; n = 0; while (i < 3) { j = 0; while (1) { j = j + 1; if (j >= 2) { break; } n = n + 1; } i = i + 1; }", "n", 3.0);
+        assert_var("x = 5; while (0) { x = 1; }", "x", 5.0);
+    }
+
+    #[test]
+    fn labels_resolve_to_lines() {
+        // Jumping past the last line goes back to the start, where Mindustry restarts the program anyway
+        assert_eq!(compile_ok("if (a) { x = 1; }"), "jump 0 equal a 0\nset x 1\n");
+        let code = compile_ok("while (x < 5) { x = x + 1; if (x == 2) { break; } }");
+        assert_eq!(code, "jump 0 greaterThanEq x 5\nop add x x 1\njump 4 notEqual x 2\njump 0 always\njump 1 lessThan x 5\n");
+        let code = compile_ok("x = 0; while (x < 5) { if (x == 3) { x = 10; } else { x = x + 1; } } y = x;");
+        assert_eq!(code.lines().last(), Some("set y x"), "{code}");
+        assert_var("x = 0; while (x < 5) { if (x == 3) { x = 10; } else { x = x + 1; } } y = x;", "y", 10.0);
+    }
+
+    #[test]
+    fn functions_are_called_and_return() {
+        let code = compile_ok("fn f(a) { if (a > 1) { return 1; } return 2; } y = f(q); if (y) { z = 1; }");
+        // Functions come after the main program, and their labels count the lines before them
+        assert_eq!(code, "\
+set f.a q
+op add f:ret @counter 1
+jump 7 always
+set y f:val
+jump 6 equal y 0
+set z 1
+end
+jump 10 lessThanEq f.a 1
+set f:val 1
+set @counter f:ret
+set f:val 2
+set @counter f:ret
+");
+        let code = "fn f(a, b) { return a * b + 1; }";
+        assert_var(&format!("{code} x = f(2, 3);"), "x", 7.0);
+        assert_var(&format!("{code} x = f(2, 3) + f(1, f(2, 2));"), "x", 7.0 + 6.0);
+        assert_var("fn f(a) { if (a > 1) { return 1; } return 2; } x = f(5) * 10 + f(0);", "x", 12.0);
+        for (code, message) in [
+            ("fn f(a) { return a; } x = f(1, 2);", "function `f` takes 1 argument but 2 were supplied"),
+            ("fn f(a, b) { return a; } x = f(1);", "function `f` takes 2 arguments but 1 was supplied"),
+            ("fn f(a) { return a; } x = g(1);", "cannot find function `g`"),
+        ] {
+            assert_eq!(compile_err(code)[0].message(), message, "{code}");
+        }
+    }
+
+    #[test]
+    fn recursion_uses_the_stack() {
+        let code = "fn f(n) { if (n < 1) { return 0; } return f(n - 1) + n; }";
+        assert_var(&format!("{code} x = f(4);"), "x", 10.0);
+        assert_var("fn f(n) { if (n < 2) { return n; } return f(n - 1) + f(n - 2); } x = f(7);", "x", 13.0);
+        assert_var("fn even(n) { if (n == 0) { return 1; } return odd(n - 1); } fn odd(n) { if (n == 0) { return 0; } return even(n - 1); } x = even(6); y = odd(6);", "y", 0.0);
+        let output = compile(&format!("{code} x = f(4);"), &Options { stack: Some("bank1".to_string()), ..Options::default() }, &mut vec![]).unwrap().code;
+        assert!(output.contains("write f:ret bank1 stack:sp") && output.contains("read f:ret bank1 stack:sp"), "{output}");
+        // Functions that aren't recursive don't touch the stack
+        let output = compile("fn g(a) { return a + 1; } x = g(2);", &Options { stack: Some("bank1".to_string()), ..Options::default() }, &mut vec![]).unwrap().code;
+        assert!(!output.contains("write") && !output.contains("read"), "{output}");
+        assert_eq!(
+            compile_err(&format!("{code} x = f(4);"))[0].message(),
+            "recursive call to `f` needs a call stack, pass `--stack <memory cell>` to enable one",
+        );
+    }
+
+    #[test]
+    fn inline_functions_are_copied_in() {
+        let code = compile_ok("inline fn dbl(v) { return v * 2; } x = dbl(a) + dbl(3);");
+        // Constant arguments are substituted straight into the body and folded, and there's nothing to jump to and back from
+        assert!(code.contains("op mul dbl:val a 2") && code.contains("set dbl:val 6"), "{code}");
+        assert!(!code.contains("@counter") && !code.contains("end"), "{code}");
+        assert_var("inline fn dbl(v) { return v * 2; } a = 5; x = dbl(a) + dbl(3);", "x", 16.0);
+        assert_var("inline fn f(v) { if (v > 2) { return 1; } return 2; } x = f(3) * 10 + f(1);", "x", 12.0);
+        assert_var("inline fn f(v) { return v + 1; } fn g(n) { return f(n) * 2; } x = g(2);", "x", 6.0);
+        assert_eq!(compile_err("inline fn f(v) { return f(v); } x = f(1);")[0].message(), "inline function `f` can't call itself");
+        let code = "inline fn g(v) { return f(v - 1) + v; } fn f(n) { if (n < 1) { return 0; } return g(n); } x = f(3);";
+        assert_eq!(compile_err(code)[0].message(), "inline function `g` can end up calling itself through `f`");
+    }
+
+    #[test]
+    fn unary_operators_bind_tightest() {
+        let code = "b = 4; c = 6; a = -b * c; d = !b + 1; g = abs(b - c) * 2; h = --b; i = -b ^^ 2; j = abs -3 + 5; k = abs(-3 + 1);";
+        for (var, expected) in [("a", -24.0), ("d", 1.0), ("g", 4.0), ("h", 4.0), ("i", 16.0), ("j", 8.0), ("k", 2.0)] {
+            assert_var(code, var, expected);
+        }
+        let code = compile_ok("e = ~b + 1; f = sqrt b + 1; l = sqrt(b) * 2;");
+        for expected in ["op not __0 b _\nop add e __0 1", "op sqrt __0 b _\nop add f __0 1", "op add f __0 1\nop mul l __0 2"] {
+            assert!(code.contains(expected), "missing `{expected}` in {code}");
+        }
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        for (expr, expected) in [
+            ("2 ^^ 3 ^^ 2", 512.0),
+            ("2 * 3 ^^ 2", 18.0),
+            ("-2 ^^ 2", 4.0),
+            ("1 + 2 << 1", 6.0),
+            ("10 - 4 - 3", 3.0),
+            ("16 / 4 / 2", 2.0),
+            ("1 < 2 == 1", 1.0),
+            ("5 & 3 | 8", 9.0),
+            ("2 ^ 3 & 1", 3.0),
+            ("6 | 1 ^ 3", 6.0),
+            ("1 + 2 max 4", 4.0),
+            ("4 max 1 + 2 * 3", 7.0),
+            ("5 max 9 min 1", 1.0),
+        ] {
+            assert_var(&format!("x = {expr};"), "x", expected);
+        }
+    }
+
+    #[test]
+    fn builtins_called_like_functions() {
+        assert_eq!(compile_ok("x = noise(a, b);"), "op noise x a b\n");
+        assert!(compile_ok("x = len(a - 1, b) * angle(c, d);").contains("op angle"));
+        assert_var("a = 3; b = 8; x = min(a, b) * max(a, b);", "x", 24.0);
+        assert_var("x = max(1, 2) + 1;", "x", 3.0);
+        assert_var("a = 3; x = max(a - 5, min(a, 1)) * 2;", "x", 2.0);
+        for (code, message, width) in [
+            ("x = max(a);", "`max` takes 2 arguments but 1 was supplied", 6),
+            ("x = min(a, b, c);", "`min` takes 2 arguments but 3 were supplied", 12),
+            ("x = max();", "`max` takes 2 arguments but 0 were supplied", 5),
+        ] {
+            let errors = compile_err(code);
+            assert_eq!(errors[0].message(), message);
+            let span = errors[0].span();
+            assert_eq!((span.col, span.end - span.start), (5, width), "{code}");
+        }
+    }
+
+    #[test]
+    fn every_op_is_available() {
+        let binary = [
+            ("a ^^ b", "pow"), ("a * b", "mul"), ("a / b", "div"), ("a idiv b", "idiv"), ("a % b", "mod"),
+            ("a + b", "add"), ("a - b", "sub"), ("a << b", "shl"), ("a >> b", "shr"), ("a >>> b", "ushr"),
+            ("a < b", "lessThan"), ("a <= b", "lessThanEq"), ("a > b", "greaterThan"), ("a >= b", "greaterThanEq"),
+            ("a == b", "equal"), ("a != b", "notEqual"), ("a === b", "strictEqual"),
+            ("a & b", "and"), ("a ^ b", "xor"), ("a | b", "or"), ("a && b", "land"),
+            ("a max b", "max"), ("a min b", "min"), ("a angle b", "angle"), ("a ang b", "angle"), ("a angleDiff b", "angleDiff"),
+            ("a len b", "len"), ("a noise b", "noise"), ("a logn b", "logn"), ("a emod b", "emod"),
+            ("max(a, b)", "max"), ("min(a, b)", "min"), ("angle(a, b)", "angle"), ("ang(a, b)", "angle"),
+            ("angleDiff(a, b)", "angleDiff"), ("len(a, b)", "len"), ("noise(a, b)", "noise"), ("logn(a, b)", "logn"),
+            ("emod(a, b)", "emod"), ("idiv(a, b)", "idiv"),
+        ];
+        for (expr, op) in binary {
+            assert_eq!(compile_ok(&format!("x = {expr};")), format!("op {op} x a b\n"), "{expr}");
+        }
+        let unary = [
+            ("~a", "not"), ("abs a", "abs"), ("sign a", "sign"), ("ln a", "log"), ("log a", "log10"), ("log10 a", "log10"),
+            ("floor a", "floor"), ("ceil a", "ceil"), ("ciel a", "ceil"), ("round a", "round"), ("sqrt a", "sqrt"),
+            ("rand a", "rand"), ("sin a", "sin"), ("cos a", "cos"), ("tan a", "tan"), ("asin a", "asin"), ("acos a", "acos"),
+            ("atan a", "atan"), ("sqrt(a)", "sqrt"),
+        ];
+        for (expr, op) in unary {
+            assert_eq!(compile_ok(&format!("x = {expr};")), format!("op {op} x a _\n"), "{expr}");
+        }
+        assert_eq!(compile_ok("x = -a;"), "op sub x 0 a\n");
+        assert_eq!(compile_ok("x = !a;"), "op equal x a 0\n");
+        // Mindustry has no logical or, so both sides are turned into 0 or 1 first
+        for (a, b, expected) in [(0.0, 0.0, 0.0), (2.0, 0.0, 1.0), (0.0, -3.0, 1.0), (2.0, 5.0, 1.0)] {
+            assert_var(&format!("a = {a}; b = {b}; x = a || b;"), "x", expected);
+        }
+        assert_var("x = 1 || 0 && 0;", "x", 1.0);
+        assert_var("x = 0 && 1 || 1;", "x", 1.0);
+        assert_var("x = 1 == 2 != 0;", "x", 0.0);
+        assert_var("a = 3; b = 4; x = a + 1 != b;", "x", 0.0);
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let code = compile_ok("x = 1; /* outer /* inner */ still comment */ y = 2; // line y = 3;\n/* a\n// b */ z = 3;\nw = 4 /**/ + 1; // */");
+        assert!(code.starts_with("set x 1\nset y 2\nset z 3\n"), "{code}");
+        assert_var("x = 1; /* x = 2; /* x = 3; */ x = 4; */ y = x; // y = 5;", "y", 1.0);
+        // Lines after a comment still point at the right place
+        let errors = compile_err("/* a\n/* b */\n*/ x = 2 +;");
+        assert_eq!((errors[0].span().line, errors[0].span().col), (3, 10));
+        assert_eq!(compile_err("/* /* */ x = 1;")[0].message(), "unterminated block comment, expected a closing `*/`");
+    }
+
+    #[test]
+    fn print_with_format_strings() {
+        let code = compile_ok("print(\"Power: {} / {}\\n\", p, c); print(\"{{x}} {}\", a); print(x); printflush(message);");
+        assert_eq!(code, "\
+print \"Power: \"
+print p
+print \" / \"
+print c
+print \"\\n\"
+print \"{x} \"
+print a
+print x
+printflush message
+");
+        let code = compile_ok("print(\"{}\", 1 + a);");
+        assert!(code.starts_with("op add ") && code.lines().count() == 2, "{code}");
+        for (code, message) in [
+            ("print(\"{} {}\", a);", "format string has 2 `{}` but 1 argument was supplied"),
+            ("print(\"{}\", a, b);", "format string has 1 `{}` but 2 arguments were supplied"),
+            ("print(\"{\", a);", "unmatched `{` in format string, use `{{` to print it"),
+            ("print(\"}\");", "unmatched `}` in format string, use `}}` to print it"),
+            ("printflush(a, b);", "`printflush` takes 1 argument but 2 were supplied"),
+            ("printflush();", "`printflush` takes 1 argument but 0 were supplied"),
+        ] {
+            assert_eq!(compile_err(code)[0].message(), message, "{code}");
+        }
+    }
+
+    #[test]
+    fn identifiers_and_builtins() {
+        assert_var("message1 = 2; x2y = message1 * 3;", "x2y", 6.0);
+        let code = compile_ok("t = @phase-fabric; d = @large-logic-display; y = @x-1; z = @x - y; c = @counter;");
+        for expected in ["set t @phase-fabric", "set d @large-logic-display", "op sub y @x 1", "op sub z @x y", "set c @counter"] {
+            assert!(code.contains(expected), "missing `{expected}` in {code}");
+        }
+        assert_eq!(compile_err("@time = 1;")[0].message(), "`@time` is a builtin and can't be assigned to");
+
+        let mut warnings = vec![];
+        compile("x = @cooper + @copper; y = @time;", &Options { keep_unused: true, ..Options::default() }, &mut warnings).unwrap();
+        let found = warnings.iter().map(|w| (w.span.col, w.message.as_str())).collect::<Vec<_>>();
+        assert_eq!(found, [(5, "unknown builtin `@cooper`, did you mean `@copper`?")]);
+    }
+
+    #[test]
+    fn number_literals() {
+        let code = compile_ok("a = 0xFF; b = 0B1010; c = 1_000_000; d = 1.5e-3; e = 2.5E+2; f = 'A'; g = '\\n'; h = 0x_ff_ff;");
+        for expected in ["set a 255", "set b 10", "set c 1000000", "set d 0.0015", "set e 250", "set f 65", "set g 10", "set h 65535"] {
+            assert!(code.contains(expected), "missing `{expected}` in {code}");
+        }
+        for (code, message) in [
+            ("x = 0x;", "expected digits after `0x`"),
+            ("x = 0b2;", "expected digits after `0b`"),
+            ("x = 12abc;", "invalid number `12abc`"),
+            ("x = 1e;", "invalid number `1e`"),
+            ("x = 9007199254740993;", "number is too large to be stored exactly, the largest is 2^53"),
+            ("x = 0x20000000000001;", "number is too large to be stored exactly, the largest is 2^53"),
+            ("x = 1e400;", "number is too large to be stored, the largest is about 1.8e308"),
+            ("x = -1e400;", "number is too large to be stored, the largest is about 1.8e308"),
+            ("x = 1e-400;", "number is too small to be stored, the smallest is about 4.9e-324"),
+            ("x = '';", "expected a character in the character literal"),
+            ("x = 'ab';", "character literals hold a single character, expected a closing `'`"),
+        ] {
+            assert_eq!(compile_err(code)[0].message(), message, "{code}");
+        }
+        assert!(compile_ok("x = 0x20000000000000;").contains("set x 9007199254740992"));
+        assert!(compile_ok("x = 9007199254740992;").contains("set x 9007199254740992"));
+        assert!(compile_ok("x = 0e-400;").contains("set x 0"));
+    }
+
+    #[test]
+    fn numbers_written_with_exponent() {
+        let code = compile_ok("a = 1e300; b = 1e200 * 1e100; c = 0.000001 / 8; d = -1.5e20; e = 1e16; f = 0.00001; g = 123456.75;");
+        for expected in ["set a 1e300", "set b 1e300", "set c 1.25e-7", "set d -1.5e20", "set e 1e16", "set f 0.00001", "set g 123456.75"] {
+            assert!(code.contains(expected), "missing `{expected}` in {code}");
+        }
+        // Every number reads back as the value it was written from
+        for n in [1e300, 1.25e-7, -1.5e20, 1e16, 0.00001, 1.0 / 3.0, 2.0f64.powi(53), f64::MIN_POSITIVE, f64::MAX] {
+            let code = compile_ok(&format!("x = {n:e};"));
+            let written = code.lines().next().unwrap().trim_start_matches("set x ");
+            assert_eq!(written.parse::<f64>(), Ok(n), "{code}");
+        }
+    }
+
+    #[test]
+    fn minus_subtracts_or_negates() {
+        let code = compile_ok("a = b - -3; c = (b)-1; d = - 5; e = -9; f = -(5); h = x-1;");
+        for expected in ["op sub a b -3", "op sub c b 1", "set d -5", "set e -9", "set f -5", "op sub h x 1"] {
+            assert!(code.contains(expected), "missing `{expected}` in {code}");
+        }
+        assert_var("b = 4; x = b - -3;", "x", 7.0);
+        assert_var("b = 4; x = (b + 1)-1;", "x", 4.0);
+        assert_var("b = 4; c = 2; x = b * -c;", "x", -8.0);
+        assert_var("b = 4; x = b-2-1;", "x", 1.0);
+    }
+
+    #[test]
+    fn registers_are_named_per_function() {
+        let code = "fn f(a, b) { return (a + b) * (a - b); } x = (p + q) * (r + s) + f(p, q);";
+        let output = compile_ok(code);
+        assert!(output.starts_with("op add __0 p q\nop add __1 r s\nop mul __0 __0 __1\n"), "{output}");
+        assert!(output.contains("op add __f.0 f.a f.b\nop sub __f.1 f.a f.b\nop mul f:val __f.0 __f.1\n"), "{output}");
+        let options = Options { register_prefix: "t".to_string(), keep_unused: true, ..Options::default() };
+        let output = compile(code, &options, &mut vec![]).unwrap().code;
+        assert!(output.contains("op mul t0 t0 t1") && output.contains("op mul f:val tf.0 tf.1"), "{output}");
+        assert_var(&format!("p = 5; q = 3; r = 1; s = 2; {code}"), "x", 8.0 * 3.0 + 16.0);
+
+        for code in ["__x = 1;", "x = __y + 1;", "fn __f(a) { return a; }", "fn f(__a) { return __a; }"] {
+            let name = code.split(|ch: char| !ch.is_alphanumeric() && ch != '_').find(|word| word.starts_with("__")).unwrap();
+            let message = format!("`{name}` starts with `__`, which is reserved for temporary variables");
+            assert_eq!(compile_err(code)[0].message(), message, "{code}");
+        }
+        let errors = compile("tx = 1;", &Options { register_prefix: "t".to_string(), ..Options::default() }, &mut vec![]).unwrap_err();
+        assert_eq!(errors[0].message(), "`tx` starts with `t`, which is reserved for temporary variables");
+    }
+
+    #[test]
+    fn constants_fold_like_mindustry() {
+        assert_eq!(compile_ok("x = 2 * 3 + 4;"), "set x 10\n");
+        assert_eq!(compile_ok("x = -7 idiv 2;"), "set x -4\n");
+        assert_eq!(compile_ok("x = emod(-7, 3);"), "set x 2\n");
+        assert_eq!(compile_ok("x = 5 / 0;"), "set x 0\n");
+        assert_eq!(compile_ok("x = round(-2.5);"), "set x -2\n");
+        assert_eq!(compile_ok("x = -1 >>> 60;"), "set x 15\n");
+        assert_eq!(compile_ok("x = 0.1 + 0.2 == 0.3;"), "set x 1\n");
+    }
+
+    #[test]
+    fn identities_keep_side_effects() {
+        assert_eq!(compile_ok("x = (y + z) * 1 + 0;"), "op add x y z\n");
+        // A variable or builtin might not hold a number, which the operation would turn into one
+        assert_eq!(compile_ok("x = y * 1;"), "op mul x y 1\n");
+        assert_eq!(compile_ok("x = @unit + 0;"), "op add x @unit 0\n");
+        assert_eq!(compile_ok("x = 0 * y;"), "set x 0\n");
+        assert!(compile_ok("fn f(v) { return v; } x = 0 * f(1);").contains("jump"));
+        assert!(compile_ok("x = rand 10;").contains("op rand"));
+    }
+
+    #[test]
+    fn target_read_on_the_right() {
+        assert_var("x = 3; y = 4; x = (y + 1) * x;", "x", 15.0);
+        assert_var("x = 3; y = 4; x = y * (x + 1);", "x", 16.0);
+        assert_var("x = 3; x = x - x * 2;", "x", -3.0);
+    }
+
+    #[test]
+    fn target_read_on_both_sides() {
+        assert_var("x = 3; x = (x + 1) * (x + 2);", "x", 20.0);
+        assert_var("x = 3; y = 2; x = y + x * (y - x);", "x", -1.0);
+        assert_var("x = 3; x = x ^^ 2 - x;", "x", 6.0);
+    }
+
+    #[test]
+    fn target_read_under_unary() {
+        assert_var("x = 3; x = -(x + 1) * x;", "x", -12.0);
+        assert_var("x = 3; x = abs(x - 10) + x;", "x", 10.0);
+    }
+
+    #[test]
+    fn hyphen_in_builtin_name() {
+        let code = compile_ok("print(@silicon-smelter); print(@graphite-press); d = @large-logic-display; dx = @thisx - ux; y = @x-1;");
+        for expected in ["print @silicon-smelter", "print @graphite-press", "set d @large-logic-display", "op sub dx @thisx ux", "op sub y @x 1"] {
+            assert!(code.contains(expected), "missing `{expected}` in {code}");
+        }
+        // Without spaces a `-` followed by a letter is part of the name, whether or not the compiler knows it
+        assert!(compile_ok("dx = @thisx-ux;").contains("set dx @thisx-ux"));
+    }
+
+    #[test]
+    fn target_read_by_builtin_call() {
+        assert_var("x = 3; y = 5; x = max(x, y) + x;", "x", 8.0);
+        assert_var("x = 3; x = min(x * 2, 100) - x;", "x", 3.0);
+    }
+
+    #[test]
+    fn target_passed_to_function() {
+        assert_var("fn sq(v) { return v * v; } x = 3; x = sq(x) + x;", "x", 12.0);
+        assert_var("fn sq(v) { return v * v; } x = 3; x = (x + 1) * sq(x);", "x", 36.0);
+    }
+
+    #[test]
+    fn compound_argument_inside_expression() {
+        let values = "a = 1; b = 2; c = 3; d = 4; e = 5; g = 6;";
+        assert_var(&format!("fn f(v) {{ return v; }} {values} x = (a + b) * f((c + d) * (e + g));"), "x", 3.0 * 77.0);
+        assert_var(&format!("fn f(v) {{ return v - 1; }} {values} x = (a + b) * (c - f(d * e + g));"), "x", 3.0 * (3.0 - 25.0));
+        assert_var(&format!("inline fn f(v) {{ return v * 2; }} {values} x = (a + b) * f((c + d) * (e + g));"), "x", 3.0 * 154.0);
+        assert_var(&format!("inline fn f(v) {{ return (v + 1) * (v + 2); }} {values} x = (a + b) * (c - f(d + e));"), "x", 3.0 * (3.0 - 110.0));
+    }
+
+    #[test]
+    fn target_passed_to_inline_function() {
+        assert_var("inline fn twice(v) { return v * 2; } x = 3; x = (x + 1) * twice(x);", "x", 24.0);
+        assert_var("inline fn add(a, b) { return a + b; } x = 3; x = add(x, 1) * x;", "x", 12.0);
+    }
+
+    #[test]
+    fn arguments_before_a_call_are_kept() {
+        let code = "fn h(v) { return v; } fn g(a, b) { return a - b; } p = 1; q = 2; r = 3; s = 4; t = 5;";
+        assert_var(&format!("{code} x = g(p + q, (r + s) * h(t));"), "x", 3.0 - 35.0);
+        assert_var(&format!("{code} x = g(p + q, h(r + s));"), "x", 3.0 - 7.0);
+        assert_var(&format!("{code} x = g(p * q, g(r + s, h(t) + 1));"), "x", 2.0 - 1.0);
     }
 
-    /*println!("Making AST...");
-    let st = "6/2*(1+sq(2, 4))";
-    let expr = Expression::make_ast(&lex(&st.chars().collect::<Vec<char>>()));
-    println!("Input: {st}");
-    println!("Generated AST: {expr:?}");
-    println!("Output: {}", expr.unwrap().generate_code(&VarStorage::Identifier(String::from("result")), &function_table));*/
+    #[test]
+    fn inline_call_as_last_argument() {
+        let code = "inline fn dbl(v) { return (v + 1) * (v + 2); } fn g(a, b) { return a + b; } p = 1; q = 2; r = 3;";
+        assert_var(&format!("{code} x = g(p + q, dbl(r));"), "x", 3.0 + 20.0);
+        assert_var(&format!("{code} x = g(p + q, dbl(r) * 2);"), "x", 3.0 + 40.0);
+        assert_var(&format!("{code} x = g(p + q, g(r * 2, dbl(r + 1)));"), "x", 3.0 + 6.0 + 30.0);
+    }
 
-    /*for i in tokens {
-        write!(out, "{i:?} ")?;
-    }*/
+    #[test]
+    fn parameter_read_in_recursive_call() {
+        assert_var("fn fact(n) { if (n < 2) { return 1; } return n * fact(n - 1); } x = fact(5);", "x", 120.0);
+        assert_var("fn fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); } x = fib(10);", "x", 55.0);
+    }
 
-    /*out.write(output_str.as_bytes())
-        .expect("Unable to write to output!");
-    drop(code);*/
-    Ok(())
+    #[test]
+    fn argument_before_recursive_call_is_saved() {
+        let code = "fn g(a, b) { return a + b; } fn f(n) { if (n < 1) { return 0; } return g(n + 1, f(n - 1)); }";
+        assert_var(&format!("{code} x = f(3);"), "x", 4.0 + 3.0 + 2.0);
+        assert_var(&format!("{code} x = f(5) * 2;"), "x", 2.0 * (6.0 + 5.0 + 4.0 + 3.0 + 2.0));
+    }
+
+    #[test]
+    fn peephole_forwards_copies() {
+        let code = compile_ok("fn sq(v) { return v * v; } x = sq(3) + 1;");
+        assert!(code.contains("op add x sq:val 1"), "{code}");
+        assert_eq!(code.matches("set @counter sq:ret").count(), 1, "{code}");
+    }
+
+    #[test]
+    fn peephole_keeps_control_flow() {
+        let code = "i = 0; s = 0; while (i < 10) { i = i + 1; if (i == 3) { continue; } if (i > 7) { if (s > 0) { break; } } s = s + i; }";
+        assert_var(code, "s", 1.0 + 2.0 + 4.0 + 5.0 + 6.0 + 7.0);
+        assert_var("x = 0; if (y > 0) { if (y > 1) { x = 2; } else { x = 1; } } else { x = 3; }", "x", 3.0);
+    }
+
+    #[test]
+    fn unused_variables_are_removed() {
+        let mut warnings = vec![];
+        let code = compile("a = 1; b = a * 2; print(c); c = 3;", &Options::default(), &mut warnings).unwrap().code;
+        assert_eq!(code, "print c\nset c 3\n");
+        let messages = warnings.iter().map(|w| w.message.as_str()).collect::<Vec<&str>>();
+        assert_eq!(messages, [
+            "variable `a` doesn't affect the program, so it was removed",
+            "variable `b` doesn't affect the program, so it was removed",
+        ]);
+    }
+
+    #[test]
+    fn stores_read_elsewhere_are_kept() {
+        let keeps = |code: &str, kept: &str| {
+            let output = compile(code, &Options::default(), &mut vec![]).unwrap().code;
+            assert!(output.contains(kept), "`{kept}` was removed from:\n{output}");
+        };
+        keeps("n = n + 1;", "op add n n 1");
+        keeps("x = 5; $sensor y @unit x$", "set x 5");
+        keeps("fn show() { print(g); } g = 2; show();", "set g 2");
+        keeps("x = 5; $jump 0 always$", "set x 5");
+    }
+
+    #[test]
+    fn repeated_operations_reuse_results() {
+        let code = compile_ok("d = len(ux - x, uy - y); a = angle(ux - x, uy - y);");
+        assert_eq!(code.matches("op sub").count(), 2, "{code}");
+        assert_eq!(compile_ok("a = rand 5 + rand 5;").matches("op rand").count(), 2);
+        assert_eq!(compile_ok("a = @time * 2 + @time * 2;").matches("op mul").count(), 2);
+        assert_var("b = 1; c = 2; a = b + c; b = 5; d = c + b;", "d", 7.0);
+        assert_var("x = 1; x = x + 1; y = x + 1;", "y", 3.0);
+    }
+
+    #[test]
+    fn programs_over_the_limit_are_rejected() {
+        let code = "fn f(v) { print(v); print(v); }\nf(1);\nprint(1); print(2); print(3);";
+        let options = Options { max_instructions: 8, ..Options::default() };
+        let errors = compile(code, &options, &mut vec![]).unwrap_err();
+        assert_eq!(errors[0].span().line, 2);
+        assert_eq!(errors[0].message(), "the program is 10 instructions long, but a processor holds at most 8 \
+            (the limit can be changed with `--max-instructions`)\n  main program: 7 instructions\n    line 2:1: 3 instructions\n    \
+            line 3:1: 1 instruction\n    line 3:11: 1 instruction\n    line 3:21: 1 instruction\n  fn f: 3 instructions\n    \
+            line 1:11: 1 instruction\n    line 1:21: 1 instruction");
+        let options = Options { max_instructions: 10, ..Options::default() };
+        assert!(compile(code, &options, &mut vec![]).is_ok());
+    }
+
+    #[test]
+    fn cost_report_counts_blocks_and_loops() {
+        let options = Options { cost: true, ..Options::default() };
+        let code = "i = 0; while (i < 10) { i = i + 1; } print(i); $wait 1$";
+        let report = compile(code, &options, &mut vec![]).unwrap().cost.unwrap();
+        assert_eq!(report, "\
+main program: one pass takes at most 6 instructions (micro 3.00, logic 0.75, hyper 0.24 ticks) plus `wait`
+  block at lines 0-1: 2 instructions (micro 1.00, logic 0.25, hyper 0.08 ticks)
+  block at lines 2-3: 2 instructions (micro 1.00, logic 0.25, hyper 0.08 ticks)
+  block at lines 4-5: 2 instructions (micro 1.00, logic 0.25, hyper 0.08 ticks) plus `wait`
+  loop at lines 2-3: 2 instructions (micro 1.00, logic 0.25, hyper 0.08 ticks) per iteration
+");
+        let code = "fn f(n) { if (n < 1) { return 0; } return f(n - 1); } x = f(3); print(x);";
+        let options = Options { cost: true, stack: Some("cell1".to_string()), ..Options::default() };
+        let report = compile(code, &options, &mut vec![]).unwrap().cost.unwrap();
+        assert!(report.contains("plus recursion into `f`"), "{report}");
+        let code = "fn f(a) { print(a * 2 + 1); } f(3); f(4);";
+        let options = Options { cost: true, ..Options::default() };
+        let report = compile(code, &options, &mut vec![]).unwrap().cost.unwrap();
+        assert!(report.starts_with("main program: one pass takes at most 15 instructions"), "{report}");
+        assert!(report.contains(", calling f 2 times\n"), "{report}");
+    }
 }
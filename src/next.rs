@@ -1,15 +1,10 @@
-use crate::{is, lex::Token};
+use crate::is;
 
 pub fn next_char(i: &mut usize, v: &[char]) -> Option<char> {
-    *i = *i + 1;
+    *i += 1;
     v.get(*i - 1).copied()
 }
 
-pub fn next_token(i: &mut usize, v: &[Token]) -> Option<Token> {
-    *i = *i + 1;
-    v.get(*i - 1).cloned()
-}
-
 pub fn next_identifier(i: &mut usize, v: &[char]) -> String {
     let mut out = String::new();
     while let Some(ch) = v.get(*i) {
@@ -23,35 +18,111 @@ pub fn next_identifier(i: &mut usize, v: &[char]) -> String {
     out
 }
 
-pub fn next_number(i: &mut usize, v: &[char], negative: bool) -> f64 {
-    let mut out = 0.0;
-    'lloop: while let Some(ch) = v.get(*i) {
-        if ('0'..='9').contains(ch) {
-            out *= 10.0;
-            out += (*ch as u8 - '0' as u8) as f64;
-            *i += 1;
-        } else if *ch == '.' {
+/// Reads a builtin such as `@counter` or `@phase-fabric`, starting at the `@`.
+/// A `-` is part of the name only if a letter follows it, so `@x-1` is still a subtraction.
+pub fn next_builtin(i: &mut usize, v: &[char]) -> String {
+    let mut out = String::from("@");
+    *i += 1;
+    loop {
+        out.push_str(&next_identifier(i, v));
+        match (v.get(*i), v.get(*i + 1)) {
+            (Some('-'), Some(ch)) if ch.is_ascii_alphabetic() => {
+                *i += 1;
+                out.push('-');
+            }
+            _ => break out,
+        }
+    }
+}
+
+/// Reads a number literal: decimal with an optional fraction and exponent such as `1.5e-3`, or `0x` hexadecimal
+/// or `0b` binary. Digits can be separated with `_`. Decimals are parsed exactly rather than digit by digit,
+/// so the number written out is the shortest text that reads back as the same value. Integers in any radix
+/// have to be at most 2^53, so they aren't rounded; write larger numbers with an exponent. Numbers too large
+/// or too small for a 64 bit float are rejected rather than becoming infinity or zero.
+pub fn next_number(i: &mut usize, v: &[char]) -> Result<f64, String> {
+    let start = *i;
+    let radix = match (v.get(*i), v.get(*i + 1)) {
+        (Some('0'), Some('x' | 'X')) => 16,
+        (Some('0'), Some('b' | 'B')) => 2,
+        _ => 10,
+    };
+    let value = if radix == 10 {
+        let mut text = next_digits(i, v, 10);
+        if let Some('.') = v.get(*i) {
             *i += 1;
-            let mut decimal = 0.0;
-            let mut div10 = 1;
-            while let Some(ch) = v.get(*i) {
-                if ('0'..='9').contains(ch) {
-                    div10 *= 10;
-                    decimal += (*ch as u8 - '0' as u8) as f64 / div10 as f64;
-                    *i += 1;
-                } else {
-                    out += decimal;
-                    break 'lloop;
-                }
+            text.push('.');
+            text.push_str(&next_digits(i, v, 10));
+        }
+        // Only an exponent if digits follow, otherwise the `e` is left to be reported below
+        let sign = usize::from(matches!(v.get(*i + 1), Some('+' | '-')));
+        if matches!(v.get(*i), Some('e' | 'E')) && v.get(*i + 1 + sign).is_some_and(char::is_ascii_digit) {
+            text.push('e');
+            if sign == 1 {
+                text.push(v[*i + 1]);
+            }
+            *i += 1 + sign;
+            text.push_str(&next_digits(i, v, 10));
+        }
+        if text.contains(['.', 'e']) {
+            let value = text.parse::<f64>().map_err(|e| format!("invalid number `{text}`: {e}"))?;
+            // Parsing saturates to infinity and rounds to zero instead of failing
+            if value.is_infinite() {
+                return Err("number is too large to be stored, the largest is about 1.8e308".to_string());
             }
-            out += decimal;
+            if value == 0.0 && text.split('e').next().is_some_and(|digits| digits.contains(|ch| ('1'..='9').contains(&ch))) {
+                return Err("number is too small to be stored, the smallest is about 4.9e-324".to_string());
+            }
+            value
         } else {
+            exact_integer(&text, radix)?
+        }
+    } else {
+        *i += 2;
+        let digits = next_digits(i, v, radix);
+        if digits.is_empty() {
+            return Err(format!("expected digits after `{}`", v[start..*i].iter().collect::<String>()));
+        }
+        exact_integer(&digits, radix)?
+    };
+    if v.get(*i).is_some_and(|ch| is::is_identifier_char(*ch)) {
+        next_identifier(i, v);
+        return Err(format!("invalid number `{}`", v[start..*i].iter().collect::<String>()));
+    }
+    Ok(value)
+}
+
+/// The value of an integer written with `digits` in `radix`, which has to fit in a number exactly.
+fn exact_integer(digits: &str, radix: u32) -> Result<f64, String> {
+    let value = u64::from_str_radix(digits, radix).map_err(|_| "number is too large".to_string())?;
+    // Mindustry numbers are 64 bit floats, which have 53 bits for the digits
+    if value > 1 << 53 {
+        return Err("number is too large to be stored exactly, the largest is 2^53".to_string());
+    }
+    Ok(value as f64)
+}
+
+/// Reads digits in `radix` along with any `_` separating them, returning only the digits.
+fn next_digits(i: &mut usize, v: &[char], radix: u32) -> String {
+    let mut out = String::new();
+    while let Some(ch) = v.get(*i) {
+        if ch.is_digit(radix) {
+            out.push(*ch);
+        } else if *ch != '_' {
             break;
         }
+        *i += 1;
     }
-    if negative {
-        -out
-    } else {
-        out
+    out
+}
+
+/// Reads the character after a `\` in a string or character literal, returning the character it stands for.
+pub fn next_escape(i: &mut usize, v: &[char]) -> Result<char, String> {
+    match next_char(i, v) {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some(ch @ ('\\' | '\'' | '"')) => Ok(ch),
+        Some(ch) => Err(format!("unknown escape `\\{ch}`, expected `\\n`, `\\t`, `\\\\`, `\\'` or `\\\"`")),
+        None => Err("expected an escaped character after `\\`".to_string()),
     }
 }
@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{flow, is};
+use crate::lex::{BinOp, UnOp};
+use crate::parse::expr::{JumpCond, Label, VarStorage, IR};
+
+/// Cleans up the IR code generation leaves behind, repeating until nothing changes:
+/// copies into registers are forwarded into the one instruction reading them, results are written straight into
+/// the variable they're copied to, `set a a` is dropped, jumps are pointed past jumps they land on,
+/// jumps to the next instruction are dropped, and so is code no jump can reach.
+///
+/// Only registers are forwarded, since any other variable may be read after the program restarts.
+/// Must run before `alloc::allocate_registers`, while temporaries are still registers.
+pub fn peephole(mut ir: Vec<IR>) -> Vec<IR> {
+    loop {
+        let changed = forward_copies(&mut ir)
+            | remove_self_copies(&mut ir)
+            | collapse_jump_chains(&mut ir)
+            | remove_jumps_to_next(&mut ir)
+            | remove_unreachable(&mut ir);
+        if !changed {
+            break ir;
+        }
+    }
+}
+
+/// Forwards `set %r x` into the instruction right after it and writes `op ... %r ...` followed by `set y %r`
+/// straight into `y`, as long as the register isn't read again.
+fn forward_copies(ir: &mut Vec<IR>) -> bool {
+    let is_register = |name: &str| VarStorage::register_index(name).is_some();
+    let live_out = flow::live_out(ir, &flow::successors(ir), is_register);
+    let mut removed = HashSet::new();
+    let mut idx = 0;
+    while idx + 1 < ir.len() {
+        let (first, second) = (&ir[idx], &ir[idx + 1]);
+        let Some(register) = first.def_use().0.filter(|def| is_register(def)).cloned() else {
+            idx += 1;
+            continue;
+        };
+        let (second_def, second_uses) = second.def_use();
+        let dead_after = !live_out[idx + 1].contains(&register) || second_def == Some(&register);
+        let read_by_second = second_uses.contains(&&register);
+        match (first, second) {
+            // `op add %0 a b` + `set y %0` is `op add y a b`, the operands are read before `y` is written
+            (_, IR::Set(dest, src)) if *src == register && dest != "@counter" && !live_out[idx + 1].contains(&register) => {
+                let dest = dest.clone();
+                if let Some(def) = ir[idx].operands_mut().into_iter().next() {
+                    *def = dest;
+                }
+            }
+            (IR::Set(_, value), _) if read_by_second && dead_after && *value != register => {
+                let value = value.clone();
+                // The definition comes first in the operands, so only the reads after it are replaced
+                let skip = usize::from(second_def.is_some());
+                for operand in ir[idx + 1].operands_mut().into_iter().skip(skip) {
+                    if *operand == register {
+                        *operand = value.clone();
+                    }
+                }
+                removed.insert(idx);
+                idx += 2;
+                continue;
+            }
+            _ => {
+                idx += 1;
+                continue;
+            }
+        }
+        removed.insert(idx + 1);
+        idx += 2;
+    }
+    retain_indices(ir, &removed)
+}
+
+fn remove_self_copies(ir: &mut Vec<IR>) -> bool {
+    let before = ir.len();
+    ir.retain(|fragment| !matches!(fragment, IR::Set(dest, src) if dest == src));
+    ir.len() != before
+}
+
+/// Points jumps landing on an unconditional jump at where that one goes.
+fn collapse_jump_chains(ir: &mut [IR]) -> bool {
+    let mut forwards: HashMap<Label, Label> = HashMap::new();
+    for (idx, fragment) in ir.iter().enumerate() {
+        if let IR::Label(label @ Label::Local(_)) = fragment {
+            if let Some(IR::Jump(target @ Label::Local(_), JumpCond::Always)) = ir[idx..].iter().find(|f| !matches!(f, IR::Label(_))) {
+                forwards.insert(label.clone(), target.clone());
+            }
+        }
+    }
+    let mut changed = false;
+    for fragment in ir.iter_mut() {
+        if let IR::Jump(label, _) = fragment {
+            // Following the chain as far as it goes, stopping if it loops
+            let mut seen = HashSet::from([label.clone()]);
+            let mut target = label.clone();
+            while let Some(next) = forwards.get(&target).filter(|next| seen.insert((*next).clone())) {
+                target = next.clone();
+            }
+            if target != *label {
+                *label = target;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Drops jumps to the instruction that would run next anyway.
+fn remove_jumps_to_next(ir: &mut Vec<IR>) -> bool {
+    let removed = (0..ir.len()).filter(|&idx| match &ir[idx] {
+        IR::Jump(label @ Label::Local(_), _) => ir[idx + 1..].iter()
+            .take_while(|fragment| matches!(fragment, IR::Label(_)))
+            .any(|fragment| matches!(fragment, IR::Label(next) if next == label)),
+        _ => false,
+    }).collect::<HashSet<usize>>();
+    retain_indices(ir, &removed)
+}
+
+/// Drops the instructions between an unconditional jump, `end` or return and the next label, which nothing can reach.
+fn remove_unreachable(ir: &mut Vec<IR>) -> bool {
+    let mut removed = HashSet::new();
+    let mut reachable = true;
+    for (idx, fragment) in ir.iter().enumerate() {
+        match fragment {
+            IR::Label(Label::Statement(_)) => (),
+            IR::Label(_) => reachable = true,
+            _ if !reachable => { removed.insert(idx); }
+            IR::Jump(Label::Local(_), JumpCond::Always) | IR::End => reachable = false,
+            IR::Set(dest, _) if dest == "@counter" => reachable = false,
+            _ => (),
+        }
+    }
+    retain_indices(ir, &removed)
+}
+
+/// Removes instructions writing a variable that's never read afterwards, and code no jump or call reaches,
+/// returning the script's variables that were assigned but no longer appear at all, sorted by name.
+///
+/// Mindustry restarts the program after `end`, so a variable read before it's written is still read after the last instruction.
+/// Builtins (`@...`) and linked blocks are always kept, calls and returns are assumed to read every variable,
+/// and inline logic every word in it. Inline logic that can jump skips the pass, since where it goes isn't known.
+pub fn eliminate_dead_stores(mut ir: Vec<IR>) -> (Vec<IR>, Vec<String>) {
+    let jumps = |code: &str| code.split_whitespace().any(|word| word == "jump" || word == "@counter");
+    if ir.iter().any(|fragment| matches!(fragment, IR::InlineLogic(code) if jumps(code))) {
+        return (ir, vec![]);
+    }
+    let assigned = ir.iter()
+        .filter_map(|fragment| fragment.def_use().0.filter(|def| is_script_variable(def)).cloned())
+        .collect::<HashSet<String>>();
+    loop {
+        let successors = restarting_successors(&ir);
+        let reachable = reachable(&ir, &successors);
+        let variables = ir.iter()
+            .flat_map(|fragment| match fragment {
+                IR::InlineLogic(code) => code.split_whitespace().map(str::to_string).collect(),
+                _ => fragment.def_use().1.into_iter().cloned().collect::<Vec<String>>(),
+            })
+            .filter(|name| VarStorage::register_index(name).is_none())
+            .collect::<HashSet<String>>();
+        let (defs, uses) = ir.iter().map(|fragment| match fragment {
+            IR::Jump(Label::Function(_), _) => (None, variables.iter().cloned().collect()),
+            IR::Set(dest, _) if dest == "@counter" => (None, variables.iter().cloned().collect()),
+            IR::InlineLogic(code) => (None, code.split_whitespace().map(str::to_string).collect()),
+            _ => {
+                let (def, uses) = fragment.def_use();
+                (def.cloned(), uses.into_iter().cloned().collect())
+            }
+        }).unzip::<_, _, Vec<Option<String>>, Vec<Vec<String>>>();
+        let live_out = flow::liveness(&successors, &defs, &uses);
+        let removed = (0..ir.len()).filter(|&idx| match &ir[idx] {
+            IR::Label(_) => false,
+            _ if !reachable[idx] => true,
+            _ => defs[idx].as_ref().is_some_and(|def| {
+                !live_out[idx].contains(def) && !def.starts_with('@') && !is::is_linked_block(def)
+            }),
+        }).collect::<HashSet<usize>>();
+        // Removing what a branch skipped can leave the jump around it pointing at the next instruction
+        if !(retain_indices(&mut ir, &removed) | remove_jumps_to_next(&mut ir)) {
+            break;
+        }
+    }
+    let remaining = ir.iter()
+        .flat_map(|fragment| {
+            let (def, uses) = fragment.def_use();
+            def.into_iter().chain(uses)
+        })
+        .collect::<HashSet<&String>>();
+    let mut removed = assigned.into_iter().filter(|name| !remaining.contains(name)).collect::<Vec<String>>();
+    removed.sort();
+    (ir, removed)
+}
+
+/// Whether `name` is a variable written in the script, rather than a temporary, parameter or one the compiler adds.
+fn is_script_variable(name: &str) -> bool {
+    VarStorage::register_index(name).is_none() && !name.starts_with('@') && !name.contains(['.', ':'])
+}
+
+/// `flow::successors`, with the end of the main program going back to its start like Mindustry does.
+fn restarting_successors(ir: &[IR]) -> Vec<Vec<usize>> {
+    let mut successors = flow::successors(ir);
+    let main_end = ir.iter().position(|fragment| matches!(fragment, IR::Label(Label::Function(_)))).unwrap_or(ir.len());
+    for (idx, next) in successors.iter_mut().enumerate().take(main_end) {
+        if next.is_empty() && !matches!(&ir[idx], IR::Set(dest, _) if dest == "@counter") {
+            next.push(0);
+        }
+    }
+    successors
+}
+
+/// Which instructions can run, starting from the first one and following both jumps and calls.
+fn reachable(ir: &[IR], successors: &[Vec<usize>]) -> Vec<bool> {
+    let mut reachable = vec![false; ir.len()];
+    let mut stack = if ir.is_empty() { vec![] } else { vec![0] };
+    while let Some(idx) = stack.pop() {
+        if std::mem::replace(&mut reachable[idx], true) {
+            continue;
+        }
+        stack.extend(&successors[idx]);
+        if let IR::Jump(function @ Label::Function(_), _) = &ir[idx] {
+            stack.extend(ir.iter().position(|fragment| matches!(fragment, IR::Label(label) if label == function)));
+        }
+    }
+    reachable
+}
+
+/// Reuses the result of an operation already computed earlier in the same basic block, as long as a variable
+/// still holds it, by copying that variable instead of computing it again.
+///
+/// Blocks end at labels other than `Label::Statement`, jumps, returns and inline logic, which may change any variable.
+/// `rand`, `noise`, memory reads and builtins such as `@time` give a new value every time.
+pub fn number_values(mut ir: Vec<IR>) -> Vec<IR> {
+    let mut values = Values::default();
+    for fragment in ir.iter_mut() {
+        let operation = match &*fragment {
+            IR::Bop(op, _, left, right) if *op != BinOp::Noise => {
+                let mut operands = vec![values.of(left), values.of(right)];
+                if op.is_commutative() {
+                    operands.sort_unstable();
+                }
+                Some((op.code(), operands))
+            }
+            IR::Uop(op, _, operand) if *op != UnOp::Rand => Some((op.code(), vec![values.of(operand)])),
+            _ => None,
+        };
+        if let Some(operation) = operation {
+            values.compute(fragment, operation);
+            continue;
+        }
+        match fragment {
+            IR::Set(dest, src) if dest != "@counter" => {
+                let value = values.of(src);
+                values.assign(dest.clone(), value);
+            }
+            IR::Label(Label::Statement(_)) => (),
+            IR::Label(_) | IR::Jump(..) | IR::End | IR::Set(..) | IR::InlineLogic(_) => values = Values::default(),
+            _ => {
+                if let Some(dest) = fragment.def_use().0.cloned() {
+                    let value = values.fresh();
+                    values.assign(dest, value);
+                }
+            }
+        }
+    }
+    ir
+}
+
+/// What `number_values` knows about the variables in the current block. Equal numbers mean equal values.
+#[derive(Default)]
+struct Values {
+    /// The value number each variable holds.
+    numbers: HashMap<String, usize>,
+    /// The value number of each operation done, by its code and the value numbers of its operands.
+    computed: HashMap<(&'static str, Vec<usize>), usize>,
+    /// A variable that held each value number when it was last written.
+    holders: HashMap<usize, String>,
+    next: usize,
+}
+impl Values {
+    fn fresh(&mut self) -> usize {
+        self.next += 1;
+        self.next - 1
+    }
+    fn of(&mut self, operand: &str) -> usize {
+        if operand.starts_with('@') {
+            return self.fresh();
+        }
+        match self.numbers.get(operand) {
+            Some(&value) => value,
+            None => {
+                let value = self.fresh();
+                self.assign(operand.to_string(), value);
+                value
+            }
+        }
+    }
+    /// A variable still holding `value`, if there is one.
+    fn holder(&self, value: usize) -> Option<&String> {
+        self.holders.get(&value).filter(|holder| self.numbers.get(*holder) == Some(&value))
+    }
+    fn assign(&mut self, dest: String, value: usize) {
+        if self.holder(value).is_none() {
+            self.holders.insert(value, dest.clone());
+        }
+        self.numbers.insert(dest, value);
+    }
+    /// Records the operation `fragment` does, replacing it with a copy if its result is already held by a variable.
+    fn compute(&mut self, fragment: &mut IR, operation: (&'static str, Vec<usize>)) {
+        let dest = fragment.def_use().0.expect("operations write their result").clone();
+        let value = match self.computed.get(&operation).copied() {
+            Some(value) => {
+                if let Some(holder) = self.holder(value) {
+                    *fragment = IR::Set(dest.clone(), holder.clone());
+                }
+                value
+            }
+            None => {
+                let value = self.fresh();
+                self.computed.insert(operation, value);
+                value
+            }
+        };
+        self.assign(dest, value);
+    }
+}
+
+fn retain_indices(ir: &mut Vec<IR>, removed: &HashSet<usize>) -> bool {
+    let mut idx = 0;
+    ir.retain(|_| {
+        idx += 1;
+        !removed.contains(&(idx - 1))
+    });
+    !removed.is_empty()
+}
@@ -1,87 +1,275 @@
-use std::{collections::BTreeMap, fmt::Display};
+use std::fmt::Display;
 
-use crate::lex::{BinOp, Token, UnOp};
+use crate::span::Span;
 
 use self::expr::Expression;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FunctionType {
+    /// Emitted once after the main program and jumped to.
     Intern,
+    /// Defined with `inline fn`. The body is copied into every call instead.
     Macro,
 }
 
+/// A function defined with `fn name(params) { ... }`.
+#[derive(Debug, Clone)]
 pub struct Function {
-    ftype: FunctionType,
-    fname: String
+    pub ftype: FunctionType,
+    pub fname: String,
+    pub params: Vec<String>,
+    pub body: Vec<Statement>,
+    pub span: Span,
+}
+impl Function {
+    /// The variable a parameter is passed in. Identifiers can't contain `.`, so this never clashes with a user variable.
+    pub fn param_var(fname: &str, param: &str) -> String {
+        format!("{fname}.{param}")
+    }
+    /// The variable holding the line to return to.
+    pub fn return_address(fname: &str) -> String {
+        format!("{fname}:ret")
+    }
+    /// The variable the result is returned in.
+    pub fn return_value(fname: &str) -> String {
+        format!("{fname}:val")
+    }
+}
+
+/// A parsed script: the main program and the functions it can call.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+    pub functions: Vec<Function>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Value {
     Identifier(String),
     Num(f64),
+    Str(String),
 }
-impl Value {
-    pub fn to_string(&self) -> String {
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Identifier(ident) => ident.clone(),
-            Self::Num(n) => n.to_string(),
+            Self::Identifier(ident) => write!(f, "{ident}"),
+            // Very large and very small numbers are written with an exponent, like `1e300`, rather than digit by digit
+            Self::Num(n) if *n != 0.0 && !(1e-5..1e16).contains(&n.abs()) => write!(f, "{n:e}"),
+            Self::Num(n) => write!(f, "{n}"),
+            // Mindustry turns `\n` in strings back into newlines
+            Self::Str(text) => write!(f, "\"{}\"", text.replace('\n', "\\n")),
         }
     }
 }
 
-pub enum Statement {
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+#[derive(Debug, Clone)]
+pub enum StatementKind {
     Assignment(String, Box<Expression>),
     Expression(Expression),
+    /// `if (cond) { ... } else { ... }`. An `else if` is an else block holding a single `If`.
+    If(Expression, Vec<Statement>, Option<Vec<Statement>>),
+    /// `while (cond) { ... }`
+    While(Expression, Vec<Statement>),
+    Break,
+    Continue,
+    /// `return;` or `return value;`
+    Return(Option<Expression>),
+    /// `print(...)`, with format strings already split up, so each expression is printed in turn.
+    Print(Vec<Expression>),
+    /// `printflush(block)`
+    PrintFlush(Expression),
 }
 pub mod expr {
     use std::{fmt::Display, collections::HashMap};
 
-    use crate::lex::{BinOp, Token, UnOp};
+    use crate::error::CompileError;
+    use crate::lex::{Assoc, BinOp, SpannedToken, Token, UnOp};
+    use crate::span::Span;
+    use crate::stmt::{generate_statements, Context, STATEMENT_BUILTINS};
 
-    use super::Value;
+    use super::{Function, Value};
 
+    #[derive(Clone)]
     pub enum VarStorage {
-        Identifier(String),
+        /// A variable, and the first register that isn't holding a value still needed while it's being computed.
+        Identifier(String, usize),
         Register(usize)
     }
     impl VarStorage {
         pub fn next(&self) -> VarStorage {
+            Self::Register(self.first_free())
+        }
+        /// The first register that can be used while computing into this storage without losing a value still needed.
+        pub fn first_free(&self) -> usize {
             match self {
-                Self::Identifier(_) => Self::Register(0),
-                Self::Register(r) => Self::Register(r + 1)
+                Self::Identifier(_, free) => *free,
+                Self::Register(r) => r + 1,
+            }
+        }
+        /// Where parts of a value being computed into this storage can be kept. A variable is only written once
+        /// its whole value is known, as the expression may still read it, like `x = (y + 1) * x`.
+        /// Registers are never read by the expression computing them, so they can hold the parts themselves.
+        pub fn temporary(&self) -> VarStorage {
+            match self {
+                Self::Identifier(_, free) => Self::Register(*free),
+                Self::Register(_) => self.clone(),
+            }
+        }
+        /// The index of the register named `name`, if it is one.
+        pub fn register_index(name: &str) -> Option<usize> {
+            let index = name.strip_prefix('%')?.parse().ok()?;
+            (Self::Register(index).to_string() == name).then_some(index)
+        }
+        /// The registers that may hold values still needed while this storage is being computed into.
+        /// Registers are handed out in order, so that's every register before the first free one, other than this one.
+        pub fn live_registers(&self) -> Vec<VarStorage> {
+            match self {
+                Self::Identifier(_, free) => (0..*free).map(Self::Register).collect(),
+                Self::Register(r) => (0..*r).map(Self::Register).collect(),
             }
         }
     }
     impl Display for VarStorage {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{}", match self {
-                Self::Identifier(i) => i.clone(),
-                Self::Register(r) => format!("r{r}")
-            })
+            match self {
+                Self::Identifier(i, _) => write!(f, "{i}"),
+                // Registers are given their final names by `alloc::allocate_registers`. Until then they're named
+                // with a `%`, which can't be part of a variable.
+                Self::Register(r) => write!(f, "%{r}"),
+            }
         }
     }
+    /// The condition of a `jump`.
+    #[derive(Debug, Clone)]
+    pub enum JumpCond {
+        Always,
+        /// Jumps if the comparison between the two operands holds.
+        Compare(BinOp, String, String),
+    }
+    /// A jump target.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Label {
+        /// A label made by code generation for control flow.
+        Local(usize),
+        /// The start of a function.
+        Function(String),
+        /// The start of the code for a statement in the main program or a function body, or of code the compiler adds
+        /// around them when `None`. Used to report sizes, nothing jumps to it.
+        Statement(Option<Span>),
+        /// An absolute instruction index. Only present after `IR::resolve_labels`.
+        Line(usize),
+    }
     #[derive(Debug)]
     pub enum IR {
         Bop(BinOp, String, String, String),
         Uop(UnOp, String, String),
         Set(String, String),
-        CallJump(String),
         InlineLogic(String),
+        /// Marks the position of the instruction after it. Takes up no instruction.
+        Label(Label),
+        Jump(Label, JumpCond),
+        /// Restarts the program.
+        End,
+        /// `read dest block address`
+        Read(String, String, String),
+        /// `write value block address`
+        Write(String, String, String),
+        /// `print value`, adding to the text buffer.
+        Print(String),
+        /// `printflush block`, moving the text buffer to a message block.
+        PrintFlush(String),
     }
     impl IR {
-        pub fn make_code(ir: &[IR], functions: &HashMap<String, usize>) -> String {
+        /// Every variable or constant the instruction reads or writes. Inline logic isn't parsed, so it has none.
+        pub fn operands_mut(&mut self) -> Vec<&mut String> {
+            match self {
+                IR::Bop(_, dest, left, right) => vec![dest, left, right],
+                IR::Uop(_, dest, operand) => vec![dest, operand],
+                IR::Set(dest, src) => vec![dest, src],
+                IR::Jump(_, JumpCond::Compare(_, left, right)) => vec![left, right],
+                IR::Read(dest, block, address) => vec![dest, block, address],
+                IR::Write(value, block, address) => vec![value, block, address],
+                IR::Print(value) | IR::PrintFlush(value) => vec![value],
+                IR::InlineLogic(_) | IR::Label(_) | IR::Jump(_, JumpCond::Always) | IR::End => vec![],
+            }
+        }
+        /// The variable the instruction writes, if any, and every variable or constant it reads.
+        pub fn def_use(&self) -> (Option<&String>, Vec<&String>) {
+            match self {
+                IR::Bop(_, dest, left, right) => (Some(dest), vec![left, right]),
+                IR::Uop(_, dest, operand) | IR::Set(dest, operand) => (Some(dest), vec![operand]),
+                IR::Jump(_, JumpCond::Compare(_, left, right)) => (None, vec![left, right]),
+                IR::Read(dest, block, address) => (Some(dest), vec![block, address]),
+                IR::Write(value, block, address) => (None, vec![value, block, address]),
+                IR::Print(value) | IR::PrintFlush(value) => (None, vec![value]),
+                IR::InlineLogic(_) | IR::Label(_) | IR::Jump(_, JumpCond::Always) | IR::End => (None, vec![]),
+            }
+        }
+        /// Numbers every instruction and replaces the labels jumps point at with the index of the instruction after the label.
+        /// A label at the very end points at the first instruction, since running off the end of the program restarts it anyway.
+        pub fn resolve_labels(ir: Vec<IR>) -> Result<Vec<IR>, CompileError> {
+            let mut labels = HashMap::new();
+            let mut line = 0;
+            for fragment in &ir {
+                match fragment {
+                    IR::Label(label) => { labels.insert(label.clone(), line); }
+                    _ => line += 1,
+                }
+            }
+            for l in labels.values_mut() {
+                if *l == line {
+                    *l = 0;
+                }
+            }
+
+            let mut resolved = Vec::with_capacity(line);
+            for fragment in ir {
+                match fragment {
+                    IR::Label(_) => {}
+                    IR::Jump(Label::Line(l), cond) => resolved.push(IR::Jump(Label::Line(l), cond)),
+                    IR::Jump(label, cond) => match labels.get(&label) {
+                        Some(&l) => resolved.push(IR::Jump(Label::Line(l), cond)),
+                        None => return Err(CompileError::Codegen(Span::default(), format!("jump to undefined label {label:?}"))),
+                    },
+                    fragment => resolved.push(fragment),
+                }
+            }
+            Ok(resolved)
+        }
+        /// Writes out IR that has gone through `resolve_labels` as Mindustry logic.
+        pub fn make_code(ir: &[IR]) -> String {
             ir.iter().map(|fragment| {
                 match fragment {
                     IR::Bop(op, dest, left, right) => format!("op {} {dest} {left} {right}", op.code()),
                     IR::Uop(op, dest, operand) => format!("op {} {dest} {operand} _", op.code()),
                     IR::Set(dest, src) => format!("set {dest} {src}"),
-                    IR::CallJump(fname) => format!("set @counter {}", functions[fname]),
                     IR::InlineLogic(logic) => logic.clone(),
+                    IR::End => "end".to_string(),
+                    IR::Read(dest, block, address) => format!("read {dest} {block} {address}"),
+                    IR::Write(value, block, address) => format!("write {value} {block} {address}"),
+                    IR::Print(value) => format!("print {value}"),
+                    IR::PrintFlush(block) => format!("printflush {block}"),
+                    IR::Label(label) => unreachable!("label {label:?} left in resolved IR"),
+                    IR::Jump(Label::Line(l), JumpCond::Always) => format!("jump {l} always"),
+                    IR::Jump(Label::Line(l), JumpCond::Compare(op, left, right)) => format!("jump {l} {} {left} {right}", op.code()),
+                    IR::Jump(label, _) => unreachable!("jump to unresolved label {label:?}"),
                 }
-            }).intersperse(format!("\n")).collect::<String>()
+            }).collect::<Vec<String>>().join("\n")
         }
     }
+    /// An expression fragment along with the source it was built from.
+    #[derive(Debug, Clone)]
+    pub struct Expression {
+        pub kind: ExpressionKind,
+        pub span: Span,
+    }
     #[derive(Debug, Clone)]
-    pub enum Expression {
+    pub enum ExpressionKind {
         /// A leaf in an expression tree. Either an identifier or constant.
         Value(Value),
         /// A binary operation between two expression fragments.
@@ -92,191 +280,444 @@ pub mod expr {
         Call(String, Vec<Expression>),
         /// Inline logic
         InlineLogic(String),
-        
-        /// Not used for actual expressions, meant for tree building. Represents a floating binary operation.
-        BinaryOp(BinOp),
-        /// Not used for actual expressions, meant for tree building. Represents a floating unary operation.
-        UnaryOp(UnOp),
     }
     impl Expression {
-        pub fn make_ast(tokens: &[Token]) -> Option<Expression> {
-            let precedence_order: Vec<&[BinOp]> = vec![
-                &[BinOp::Pow],
-                &[BinOp::Mul, BinOp::Div, BinOp::IDiv, BinOp::Mod],
-                &[BinOp::Add, BinOp::Sub],
-                &[BinOp::Lsh, BinOp::Rsh],
-                &[BinOp::Less, BinOp::LessE, BinOp::Greater, BinOp::GreaterE],
-                &[BinOp::Eq, BinOp::Streq],
-                &[BinOp::Band],
-                &[BinOp::Bxor],
-                &[BinOp::Bor],
-                &[BinOp::And],
-                &[BinOp::Max, BinOp::Min, BinOp::Angle, BinOp::Len, BinOp::Noise]
-            ];
-            
-            let mut exprs = Vec::with_capacity(tokens.len());
-            let mut tok_idx = 0;
-            // Converts tokens into expression tokens, grouping up parentheses.
-            while let Some(token) = tokens.get(tok_idx) {
-                tok_idx += 1;
-                match token {
-                    Token::Identifier(ident) => {
-                        // IPEC...CEP is a function call
-                        if let Some(Token::LParen) = tokens.get(tok_idx) {
-                            tok_idx += 1;
-                            let mut start = tok_idx;
-                            let mut nesting = 0;
-                            let mut call_args = vec![];
-                            loop {
-                                match dbg!(tokens.get(tok_idx)) {
-                                    Some(Token::LParen) => { nesting += 1; }
-                                    Some(Token::RParen) => {
-                                        if nesting == 0 {
-                                            call_args.push(Self::make_ast(&tokens[start..tok_idx])?);
-                                            break;
-                                        } else {
-                                            nesting -= 1;
-                                        }
-                                    }
-                                    Some(Token::Comma) => {
-                                        // Topmost parentheses pair
-                                        if nesting == 0 {
-                                            call_args.push(Self::make_ast(&tokens[start..tok_idx])?);
-                                            start = tok_idx;
-                                        }
-                                    }
-                                    None => break,
-                                    _ => {}
-                                }
-                                tok_idx += 1;
-                            }
-                            exprs.push(Self::Call(ident.clone(), call_args));
-                        } else {
-                            exprs.push(Expression::Value(Value::Identifier(ident.clone())));
-                        }
-                    },
-                    Token::Num(n) => exprs.push(Expression::Value(Value::Num(*n))),
-    
-                    Token::BinaryOp(op) => exprs.push(Expression::BinaryOp(*op)),
-                    Token::UnaryOp(op) => exprs.push(Expression::UnaryOp(*op)),
-    
-                    Token::LParen => {
-                        let start = tok_idx;
-                        let mut nesting = 0;
-                        loop {
-                            match tokens.get(tok_idx) {
-                                Some(Token::LParen) => { nesting += 1; }
-                                Some(Token::RParen) => {
-                                    if nesting == 0 {
-                                        exprs.push(Self::make_ast(&tokens[start..tok_idx])?);
-                                    } else {
-                                        nesting -= 1;
-                                    }
-                                }
-                                None => break,
-                                _ => {}
-                            }
-                            tok_idx += 1;
+        pub fn new(kind: ExpressionKind, span: Span) -> Expression {
+            Expression { kind, span }
+        }
+        /// Builds an expression tree out of tokens. `at` is where an error is reported if there are no tokens.
+        pub fn make_ast(tokens: &[SpannedToken], at: Span) -> Result<Expression, CompileError> {
+            let mut parser = ExprParser { tokens, pos: 0, at };
+            let expr = parser.parse_expr(0)?;
+            match tokens.get(parser.pos) {
+                None => Ok(expr),
+                Some(SpannedToken { token: Token::RParen, span }) => Err(CompileError::Parse(*span, "unmatched `)`".to_string())),
+                Some(next) => Err(CompileError::Parse(next.span, "expected an operator before this expression".to_string())),
+            }
+        }
+        /// This expression as 1 if it is non-zero and 0 otherwise. Comparisons already are, so they're left as they are.
+        fn truthy(self) -> Expression {
+            match &self.kind {
+                ExpressionKind::Binary(_, op, _) if op.is_comparison() || *op == BinOp::And => self,
+                ExpressionKind::Unary(UnOp::Not, _) => self,
+                _ => {
+                    let span = self.span;
+                    let zero = Expression::new(ExpressionKind::Value(Value::Num(0.0)), span);
+                    Expression::new(ExpressionKind::Binary(Box::new(self), BinOp::Neq, Box::new(zero)), span)
+                }
+            }
+        }
+        /// Reports every call to a function that isn't in `functions`, which maps names to parameter counts,
+        /// or that has the wrong number of arguments.
+        pub fn check_calls(&self, functions: &HashMap<String, usize>, errors: &mut Vec<CompileError>) {
+            match &self.kind {
+                ExpressionKind::Binary(left, _, right) => {
+                    left.check_calls(functions, errors);
+                    right.check_calls(functions, errors);
+                }
+                ExpressionKind::Unary(_, val) => val.check_calls(functions, errors),
+                ExpressionKind::Call(ident, args) => {
+                    match functions.get(ident) {
+                        None if STATEMENT_BUILTINS.contains(&&ident[..]) => {
+                            errors.push(CompileError::Semantic(self.span, format!("`{ident}` has no value, it can only be called on its own")));
                         }
+                        None => errors.push(CompileError::Semantic(self.span, format!("cannot find function `{ident}`"))),
+                        Some(&params) if params != args.len() => errors.push(CompileError::Semantic(
+                            self.span,
+                            format!("function `{ident}` takes {params} argument{} but {} {} supplied", if params == 1 { "" } else { "s" }, args.len(), if args.len() == 1 { "was" } else { "were" }),
+                        )),
+                        Some(_) => {}
                     }
-
-                    Token::InlineAsm(logic) => exprs.push(Expression::InlineLogic(logic.to_string())),
-    
-                    _ => continue
-                };
+                    for arg in args {
+                        arg.check_calls(functions, errors);
+                    }
+                }
+                _ => {}
             }
-            Self::merge_exprs(exprs, &precedence_order)
         }
-        pub fn merge_exprs(mut exprs: Vec<Expression>, precedence_order: &[&[BinOp]]) -> Option<Expression> {
-            // Merge unary before binary operations
-            // Merge binary operations
-            for &precedence_group in precedence_order {
-                let mut expr_idx = 1;
-                while expr_idx < exprs.len() {
-                    match exprs[expr_idx] {
-                        Expression::BinaryOp(op) => {
-                            if precedence_group.contains(&op) {
-                                let (left, right) = (
-                                    exprs.remove(expr_idx - 1),
-                                    // dont add 1, the right element was moved back
-                                    exprs.remove(expr_idx),
-                                );
-                                exprs[expr_idx - 1] = Expression::Binary(Box::new(left), op, Box::new(right));
-                                expr_idx -= 1;
-                            }
-                        }
-                        _ => {}
+        /// Whether evaluating this expression calls a function.
+        pub fn contains_call(&self) -> bool {
+            match &self.kind {
+                ExpressionKind::Binary(left, _, right) => left.contains_call() || right.contains_call(),
+                ExpressionKind::Unary(_, val) => val.contains_call(),
+                ExpressionKind::Call(..) => true,
+                _ => false,
+            }
+        }
+        /// Whether computing this expression does anything besides producing its value.
+        fn has_side_effects(&self) -> bool {
+            match &self.kind {
+                ExpressionKind::Binary(left, _, right) => left.has_side_effects() || right.has_side_effects(),
+                ExpressionKind::Unary(_, val) => val.has_side_effects(),
+                ExpressionKind::Call(..) | ExpressionKind::InlineLogic(_) => true,
+                ExpressionKind::Value(_) => false,
+            }
+        }
+        /// Computes every operation on constants, and simplifies identities like `x * 1` and `x + 0`.
+        /// Identities only apply when the other operand is an operation, whose result is always a number;
+        /// a variable or builtin can hold anything, which `op add` turns into a number.
+        pub fn fold(&mut self) {
+            let num = |e: &Expression| match e.kind {
+                ExpressionKind::Value(Value::Num(n)) => Some(n),
+                _ => None,
+            };
+            let numeric = |e: &Expression| matches!(e.kind, ExpressionKind::Binary(..) | ExpressionKind::Unary(..));
+            let folded = match &mut self.kind {
+                ExpressionKind::Binary(left, op, right) => {
+                    left.fold();
+                    right.fold();
+                    match (num(left), *op, num(right)) {
+                        (Some(a), op, Some(b)) => op.eval(a, b).map(|n| ExpressionKind::Value(Value::Num(n))),
+                        (_, BinOp::Mul, Some(1.0)) | (_, BinOp::Add | BinOp::Sub, Some(0.0)) | (_, BinOp::Div | BinOp::Pow, Some(1.0))
+                            if numeric(left) => Some(left.kind.clone()),
+                        (Some(1.0), BinOp::Mul, _) | (Some(0.0), BinOp::Add, _) if numeric(right) => Some(right.kind.clone()),
+                        (_, BinOp::Mul, Some(0.0)) if !left.has_side_effects() => Some(ExpressionKind::Value(Value::Num(0.0))),
+                        (Some(0.0), BinOp::Mul, _) if !right.has_side_effects() => Some(ExpressionKind::Value(Value::Num(0.0))),
+                        _ => None,
                     }
-                    expr_idx += 1;
                 }
+                ExpressionKind::Unary(op, val) => {
+                    val.fold();
+                    num(val).and_then(|n| op.eval(n)).map(|n| ExpressionKind::Value(Value::Num(n)))
+                }
+                ExpressionKind::Call(_, args) => {
+                    args.iter_mut().for_each(Expression::fold);
+                    None
+                }
+                ExpressionKind::Value(_) | ExpressionKind::InlineLogic(_) => None,
+            };
+            if let Some(kind) = folded {
+                self.kind = kind;
             }
-            Some(exprs.remove(0))
         }
-        pub fn generate_ir(&self, storage: &VarStorage) -> Vec<IR> {
-            match self {
-                Self::Binary(left, op, right) => {
-                    let mut collected_ir: Vec<IR> = vec![];
-    
-                    // If the arguments are `Value`s, put them directly into the operations
-                    // instead of in registers first
-                    let left_arg = if let Expression::Value(v) = &**left {
-                        v.to_string()
-                    } else {
-                        collected_ir.append(&mut left.generate_ir(storage));
-                        format!("{storage}")
-                    };
-    
-                    let right_arg = if let Expression::Value(v) = &**right {
-                        v.to_string()
-                    } else {
-                        collected_ir.append(&mut right.generate_ir(&storage.next()));
-                        format!("{}", storage.next())
-                    };
-    
+        /// Collects the name and span of every call in this expression.
+        pub fn calls<'a>(&'a self, calls: &mut Vec<(&'a str, Span)>) {
+            match &self.kind {
+                ExpressionKind::Binary(left, _, right) => {
+                    left.calls(calls);
+                    right.calls(calls);
+                }
+                ExpressionKind::Unary(_, val) => val.calls(calls),
+                ExpressionKind::Call(ident, args) => {
+                    calls.push((ident, self.span));
+                    for arg in args {
+                        arg.calls(calls);
+                    }
+                }
+                _ => {}
+            }
+        }
+        /// Replaces the identifiers in `values`, either renaming them or putting constants in their place.
+        pub fn substitute(&mut self, values: &HashMap<String, Value>) {
+            match &mut self.kind {
+                ExpressionKind::Value(value @ Value::Identifier(_)) => {
+                    if let Some(replacement) = values.get(&value.to_string()) {
+                        *value = replacement.clone();
+                    }
+                }
+                ExpressionKind::Binary(left, _, right) => {
+                    left.substitute(values);
+                    right.substitute(values);
+                }
+                ExpressionKind::Unary(_, val) => val.substitute(values),
+                ExpressionKind::Call(_, args) => {
+                    for arg in args {
+                        arg.substitute(values);
+                    }
+                }
+                _ => {}
+            }
+        }
+        /// Generates IR that makes this expression usable as an operand. `Value`s are put directly into the
+        /// operations instead of in registers first, anything else is computed into `storage`.
+        pub fn generate_operand(&self, storage: &VarStorage, ctx: &mut Context) -> Result<(Vec<IR>, String), CompileError> {
+            if let ExpressionKind::Value(v) = &self.kind {
+                Ok((vec![], v.to_string()))
+            } else {
+                Ok((self.generate_ir(storage, ctx)?, storage.to_string()))
+            }
+        }
+        /// Generates a jump to `label` that is taken when this expression is true, or when it is false if `when` is false.
+        /// Comparisons are used as the jump condition directly instead of being computed first.
+        pub fn generate_jump(&self, label: Label, when: bool, ctx: &mut Context) -> Result<Vec<IR>, CompileError> {
+            if let ExpressionKind::Unary(UnOp::Not, val) = &self.kind {
+                return val.generate_jump(label, !when, ctx);
+            }
+            if let ExpressionKind::Binary(left, op, right) = &self.kind {
+                let op = if when { Some(*op) } else { op.inverse() };
+                if let Some(op) = op.filter(|op| op.is_comparison()) {
+                    let (mut ir, left_arg) = left.generate_operand(&ctx.register(0), ctx)?;
+                    let (mut right_ir, right_arg) = right.generate_operand(&ctx.register(1), ctx)?;
+                    ir.append(&mut right_ir);
+                    ir.push(IR::Jump(label, JumpCond::Compare(op, left_arg, right_arg)));
+                    return Ok(ir);
+                }
+            }
+            let (mut ir, value) = self.generate_operand(&ctx.register(0), ctx)?;
+            let op = if when { BinOp::Neq } else { BinOp::Eq };
+            ir.push(IR::Jump(label, JumpCond::Compare(op, value, "0".to_string())));
+            Ok(ir)
+        }
+        /// Generates IR computing each argument into its parameter's variable. Arguments are computed straight into
+        /// the parameters, unless a later argument calls a function that could overwrite them, or `all_temps` is set.
+        /// Those are computed into registers after `storage` first, and copied over by the second list of IR.
+        /// Every argument keeps clear of the registers `storage` and the arguments before it still need.
+        fn bind_args(args: &[(&Expression, String)], storage: &VarStorage, all_temps: bool, ctx: &mut Context) -> Result<(Vec<IR>, Vec<IR>), CompileError> {
+            let mut arg_ir = vec![];
+            let mut param_sets = vec![];
+            let mut free = storage.first_free();
+            for (idx, (arg, param_var)) in args.iter().enumerate() {
+                if all_temps || args[idx + 1..].iter().any(|(arg, _)| arg.contains_call()) {
+                    let (mut ir, operand) = arg.generate_operand(&VarStorage::Register(free), ctx)?;
+                    arg_ir.append(&mut ir);
+                    param_sets.push(IR::Set(param_var.clone(), operand));
+                    free += 1;
+                } else {
+                    arg_ir.append(&mut arg.generate_ir(&VarStorage::Identifier(param_var.clone(), free), ctx)?);
+                }
+            }
+            Ok((arg_ir, param_sets))
+        }
+        /// Generates a call that leaves its result in the function's return value.
+        ///
+        /// A call that can re-enter the function it's made from pushes that function's state onto the call stack first
+        /// and pops it afterwards. Its arguments are all computed into registers, as they may read the parameters they replace.
+        pub fn generate_call(ident: &str, args: &[Expression], storage: &VarStorage, ctx: &mut Context) -> Result<Vec<IR>, CompileError> {
+            if let Some(function) = ctx.macros.get(ident).cloned() {
+                return Self::generate_inline(&function, args, storage, ctx);
+            }
+            let params = ctx.functions.get(ident).cloned().unwrap_or_default();
+            let saved = ctx.saved_across_call(ident, storage);
+            let args = args.iter().zip(&params).map(|(arg, param)| (arg, Function::param_var(ident, param))).collect::<Vec<_>>();
+            let (mut call_ir, mut param_sets) = Self::bind_args(&args, storage, saved.is_some(), ctx)?;
+            if let Some((block, saved)) = &saved {
+                for var in saved {
+                    call_ir.push(IR::Write(var.clone(), block.clone(), Context::STACK_POINTER.to_string()));
+                    call_ir.push(IR::Bop(BinOp::Add, Context::STACK_POINTER.to_string(), Context::STACK_POINTER.to_string(), "1".to_string()));
+                }
+            }
+            call_ir.append(&mut param_sets);
+            // @counter already points past the `op`, so the jump after it is skipped on return
+            call_ir.push(IR::Bop(BinOp::Add, Function::return_address(ident), "@counter".to_string(), "1".to_string()));
+            call_ir.push(IR::Jump(Label::Function(ident.to_string()), JumpCond::Always));
+            if let Some((block, saved)) = saved {
+                for var in saved.into_iter().rev() {
+                    call_ir.push(IR::Bop(BinOp::Sub, Context::STACK_POINTER.to_string(), Context::STACK_POINTER.to_string(), "1".to_string()));
+                    call_ir.push(IR::Read(var, block.clone(), Context::STACK_POINTER.to_string()));
+                }
+            }
+            Ok(call_ir)
+        }
+        /// Copies the body of an inline function in place of a call, leaving the result in its return value.
+        ///
+        /// Parameters that the body never assigns to are replaced by constant arguments, and by variable arguments
+        /// the body can't change. The rest are bound like a regular call. `return`s jump past the end of the copy,
+        /// and the body only uses registers after the ones the caller still needs.
+        fn generate_inline(function: &Function, args: &[Expression], storage: &VarStorage, ctx: &mut Context) -> Result<Vec<IR>, CompileError> {
+            let mut body = function.body.clone();
+            let body_calls = {
+                let mut calls = vec![];
+                body.iter().for_each(|statement| statement.calls(&mut calls));
+                !calls.is_empty()
+            };
+            let mut substitutions = HashMap::new();
+            let mut bound = vec![];
+            for (arg, param) in args.iter().zip(&function.params) {
+                let param_var = Function::param_var(&function.fname, param);
+                let constant = !body.iter().any(|statement| statement.assigns(&param_var)) && match &arg.kind {
+                    ExpressionKind::Value(Value::Num(_) | Value::Str(_)) => true,
+                    ExpressionKind::Value(Value::Identifier(ident)) => !body_calls && !body.iter().any(|statement| statement.assigns(ident)),
+                    _ => false,
+                };
+                match &arg.kind {
+                    ExpressionKind::Value(value) if constant => { substitutions.insert(param_var, value.clone()); }
+                    _ => bound.push((arg, param_var)),
+                }
+            }
+            // Constant arguments usually make parts of the body constant too
+            for statement in &mut body {
+                statement.substitute(&substitutions);
+                statement.fold();
+            }
+
+            let (mut ir, mut param_sets) = Self::bind_args(&bound, storage, false, ctx)?;
+            ir.append(&mut param_sets);
+
+            let end = ctx.label();
+            ctx.inlines.push((function.fname.clone(), end.clone()));
+            let loops = std::mem::take(&mut ctx.loops);
+            let registers = std::mem::replace(&mut ctx.registers, storage.first_free());
+            let body_ir = generate_statements(&body, ctx);
+            ctx.registers = registers;
+            ctx.loops = loops;
+            ctx.inlines.pop();
+            let mut body_ir = body_ir.map_err(|mut errors| errors.remove(0))?;
+            ir.append(&mut body_ir);
+            ir.push(IR::Label(end));
+            Ok(ir)
+        }
+        pub fn generate_ir(&self, storage: &VarStorage, ctx: &mut Context) -> Result<Vec<IR>, CompileError> {
+            match &self.kind {
+                ExpressionKind::Binary(left, op, right) => {
+                    let left_storage = storage.temporary();
+                    let (mut collected_ir, left_arg) = left.generate_operand(&left_storage, ctx)?;
+                    let (mut right_ir, right_arg) = right.generate_operand(&left_storage.next(), ctx)?;
+                    collected_ir.append(&mut right_ir);
                     collected_ir.push(IR::Bop(*op, storage.to_string(), left_arg, right_arg));
-                    collected_ir
+                    Ok(collected_ir)
+                }
+                ExpressionKind::Unary(op, val) => {
+                    let (mut ir, operand) = val.generate_operand(&storage.temporary(), ctx)?;
+                    ir.push(match op {
+                        UnOp::Neg => IR::Bop(BinOp::Sub, storage.to_string(), "0".to_string(), operand),
+                        UnOp::Not => IR::Bop(BinOp::Eq, storage.to_string(), operand, "0".to_string()),
+                        _ => IR::Uop(*op, storage.to_string(), operand),
+                    });
+                    Ok(ir)
+                }
+                ExpressionKind::Call(ident, args) => {
+                    let mut call_ir = Self::generate_call(ident, args, storage, ctx)?;
+                    call_ir.push(IR::Set(storage.to_string(), Function::return_value(ident)));
+                    Ok(call_ir)
+                }
+                ExpressionKind::Value(v) => Ok(vec![IR::Set(storage.to_string(), v.to_string())]),
+                ExpressionKind::InlineLogic(logic) => Ok(vec![IR::InlineLogic(logic.clone())]),
+            }
+        }
+    }
+
+    /// A precedence-climbing parser over the tokens of a single expression.
+    /// Each token is looked at once, so parsing takes linear time.
+    struct ExprParser<'a> {
+        tokens: &'a [SpannedToken],
+        pos: usize,
+        /// Where errors about running out of tokens are reported if there are no tokens at all.
+        at: Span,
+    }
+    impl ExprParser<'_> {
+        /// The last token, for errors about the expression ending early.
+        fn end_span(&self) -> Span {
+            self.tokens.last().map_or(self.at, |t| t.span)
+        }
+        /// Parses operands joined by binary operators that bind at least as tightly as `min_precedence`.
+        fn parse_expr(&mut self, min_precedence: u8) -> Result<Expression, CompileError> {
+            let mut left = self.parse_prefix()?;
+            while let Some(SpannedToken { token: Token::BinaryOp(op), .. }) = self.tokens.get(self.pos) {
+                let (precedence, assoc) = op.precedence();
+                if precedence < min_precedence {
+                    break;
                 }
-                Self::Unary(op, val) => {
-                    if let Expression::Value(v) = &**val {
-                        vec![IR::Uop(*op, storage.to_string(), v.to_string())]
-                    } else {
-                        let mut v = val.generate_ir(storage);
-                        v.push(IR::Uop(*op, storage.to_string(), storage.next().to_string()));
-                        v
+                let op = *op;
+                self.pos += 1;
+                // Left associative operators stop the right side at their own precedence, so `a - b - c` is `(a - b) - c`
+                let right = self.parse_expr(match assoc {
+                    Assoc::Left => precedence + 1,
+                    Assoc::Right => precedence,
+                })?;
+                let span = left.span.to(right.span);
+                left = if op == BinOp::Or {
+                    // Mindustry has no logical or, so `a || b` is `(a != 0) | (b != 0)`
+                    let kind = ExpressionKind::Binary(Box::new(left.truthy()), BinOp::Bor, Box::new(right.truthy()));
+                    Expression::new(kind, span)
+                } else {
+                    Expression::new(ExpressionKind::Binary(Box::new(left), op, Box::new(right)), span)
+                };
+            }
+            Ok(left)
+        }
+        /// Parses a single operand: a value, call, parenthesized expression or inline logic,
+        /// along with any unary operators in front of it.
+        fn parse_prefix(&mut self) -> Result<Expression, CompileError> {
+            let Some(SpannedToken { token, span }) = self.tokens.get(self.pos) else {
+                return Err(match self.tokens.last() {
+                    Some(SpannedToken { token: Token::BinaryOp(op), span }) => CompileError::Parse(*span, format!("operator `{op:?}` is missing an operand")),
+                    Some(SpannedToken { token: Token::UnaryOp(op), span }) => CompileError::Parse(*span, format!("operator `{op:?}` is missing an operand")),
+                    _ => CompileError::Parse(self.end_span(), "expected an expression".to_string()),
+                });
+            };
+            let span = *span;
+            self.pos += 1;
+            let kind = match token {
+                Token::Num(n) => ExpressionKind::Value(Value::Num(*n)),
+                Token::Str(text) => ExpressionKind::Value(Value::Str(text.clone())),
+                Token::Builtin(name) => ExpressionKind::Value(Value::Identifier(name.clone())),
+                Token::Identifier(ident) => {
+                    if let Some(SpannedToken { token: Token::LParen, .. }) = self.tokens.get(self.pos) {
+                        self.pos += 1;
+                        let (args, end) = self.parse_args(ident, span)?;
+                        return Ok(Expression::new(ExpressionKind::Call(ident.clone(), args), span.to(end)));
                     }
+                    ExpressionKind::Value(Value::Identifier(ident.clone()))
+                }
+                // Unary operators bind tighter than any binary operator, so they only take the next operand
+                Token::UnaryOp(op) => {
+                    let val = self.parse_prefix()?;
+                    let span = span.to(val.span);
+                    return Ok(Expression::new(ExpressionKind::Unary(*op, Box::new(val)), span));
+                }
+                // A `-` with nothing to subtract from is a negation. Negated numbers are folded into negative numbers.
+                Token::BinaryOp(BinOp::Sub) => {
+                    let val = self.parse_prefix()?;
+                    let span = span.to(val.span);
+                    let kind = match val.kind {
+                        // Subtracting from 0 rather than negating avoids writing out `-0`
+                        ExpressionKind::Value(Value::Num(n)) => ExpressionKind::Value(Value::Num(0.0 - n)),
+                        _ => ExpressionKind::Unary(UnOp::Neg, Box::new(val)),
+                    };
+                    return Ok(Expression::new(kind, span));
                 }
-                Self::Call(ident, args) => {
-                    let mut call_ir = vec![];
-                    for (idx, expr) in args.iter().enumerate() {
-                        call_ir.append(&mut expr.generate_ir(&VarStorage::Identifier(format!("s{idx}"))));
+                // Named operators can be called like functions, `max(a, b)` is `a max b`
+                Token::BinaryOp(op) if op.is_builtin() && matches!(self.tokens.get(self.pos), Some(SpannedToken { token: Token::LParen, .. })) => {
+                    self.pos += 1;
+                    let (args, end) = self.parse_args(op.code(), span)?;
+                    let span = span.to(end);
+                    let [left, right]: [Expression; 2] = args.try_into().map_err(|args: Vec<Expression>| {
+                        CompileError::Parse(span, format!("`{}` takes 2 arguments but {} {} supplied", op.code(), args.len(), if args.len() == 1 { "was" } else { "were" }))
+                    })?;
+                    return Ok(Expression::new(ExpressionKind::Binary(Box::new(left), *op, Box::new(right)), span));
+                }
+                Token::BinaryOp(op) => return Err(CompileError::Parse(span, format!("operator `{op:?}` is missing an operand"))),
+                Token::LParen => {
+                    let mut grouped = self.parse_expr(0)?;
+                    let Some(SpannedToken { token: Token::RParen, span: end }) = self.tokens.get(self.pos) else {
+                        return Err(self.unclosed(span, "unclosed `(`".to_string()));
+                    };
+                    self.pos += 1;
+                    grouped.span = span.to(*end);
+                    return Ok(grouped);
+                }
+                Token::InlineAsm(logic) => ExpressionKind::InlineLogic(logic.to_string()),
+                Token::RParen | Token::Comma => return Err(CompileError::Parse(span, format!("expected an expression, found `{token}`"))),
+                token => return Err(CompileError::Parse(span, format!("unexpected token `{token}` in expression"))),
+            };
+            Ok(Expression::new(kind, span))
+        }
+        /// Parses the comma separated arguments of a call up to the closing `)`, returning them with the span of the `)`.
+        fn parse_args(&mut self, ident: &str, lparen: Span) -> Result<(Vec<Expression>, Span), CompileError> {
+            let mut args = vec![];
+            if let Some(SpannedToken { token: Token::RParen, span }) = self.tokens.get(self.pos) {
+                self.pos += 1;
+                return Ok((args, *span));
+            }
+            loop {
+                args.push(self.parse_expr(0)?);
+                match self.tokens.get(self.pos) {
+                    Some(SpannedToken { token: Token::Comma, .. }) => self.pos += 1,
+                    Some(SpannedToken { token: Token::RParen, span }) => {
+                        self.pos += 1;
+                        return Ok((args, *span));
                     }
-                    call_ir.push(IR::Bop(BinOp::Add, "ret".to_string(), "@counter".to_string(), "2".to_string()));
-                    call_ir.push(IR::CallJump(ident.clone()));
-                    call_ir
+                    _ => return Err(self.unclosed(lparen, format!("unclosed `(` in call to `{ident}`"))),
                 }
-                Self::Value(v) => vec![IR::Set(storage.to_string(), v.to_string())],
-                Self::InlineLogic(logic) => vec![IR::InlineLogic(logic.clone())],
-                // We should not come across values as their generation is suppressed.
-                Self::BinaryOp(_) | Self::UnaryOp(_) => unreachable!()
             }
-    
-            /*match self {
-                Self::Binary(left, op, right) => format!(
-                    "{}\n{}\n{}",
-                    left.generate_code(register),
-                    right.generate_code(register + 1),
-                    format!("op {} r{register} r{register} r{}", op.code(), register + 1)
-                ),
-                Self::Unary(op, ex) => format!("op {} r{register} {}\n{}", op.code(), ex.repr(register + 1), ex.generate_code(register + 1)),
-                Self::Value(v) => format!("set r{register} {}", v.to_string()),
-                _ => unreachable!()
-            }*/
         }
-        pub fn repr(&self, register: usize) -> String {
-            match self {
-                Self::Value(v) => v.to_string(),
-                _ => format!("r{register}")
+        /// The error for a `(` that wasn't closed. If there is a token where the `)` should be, it's pointed at instead.
+        fn unclosed(&self, lparen: Span, message: String) -> CompileError {
+            match self.tokens.get(self.pos) {
+                Some(next) => CompileError::Parse(next.span, format!("expected `)` or an operator, found `{}`", next.token)),
+                None => CompileError::Parse(lparen, message),
             }
         }
     }
 }
+
@@ -0,0 +1,53 @@
+use std::fmt::{Display, Formatter};
+
+/// A region of a script. `start` and `end` are byte offsets into the source, while `line` and `col`
+/// are the 1-based position of `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+impl Span {
+    /// Makes a span starting at the start of `self` and ending at the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end.max(self.end), ..self }
+    }
+}
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// Turns char indices (which the lexer works in) into spans.
+pub struct SourceMap {
+    /// Byte offset, line and column of every char, plus one past the end.
+    positions: Vec<(usize, usize, usize)>,
+}
+impl SourceMap {
+    pub fn new(code_chars: &[char]) -> SourceMap {
+        let mut positions = Vec::with_capacity(code_chars.len() + 1);
+        let (mut byte, mut line, mut col) = (0, 1, 1);
+        for ch in code_chars {
+            positions.push((byte, line, col));
+            byte += ch.len_utf8();
+            if *ch == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+        }
+        positions.push((byte, line, col));
+        SourceMap { positions }
+    }
+    /// Span of the chars `start..end`.
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let last = self.positions.len() - 1;
+        let (start, line, col) = self.positions[start.min(last)];
+        let (end, _, _) = self.positions[end.min(last)];
+        Span { start, end, line, col }
+    }
+}
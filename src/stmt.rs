@@ -0,0 +1,755 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::CompileError,
+    lex::{SpannedToken, Token},
+    parse::{expr::{Expression, ExpressionKind, JumpCond, Label, VarStorage, IR}, Function, FunctionType, Program, Statement, StatementKind, Value},
+    span::Span,
+};
+
+/// Functions that are built into the language and can only be called as statements, as they have no value.
+pub const STATEMENT_BUILTINS: &[&str] = &["print", "printflush"];
+
+/// Parses a whole script into the main program and its functions. A bad statement doesn't stop the rest from being parsed,
+/// so every statement's error is reported.
+pub fn parse_program(tokens: &[SpannedToken]) -> Result<Program, Vec<CompileError>> {
+    let mut parser = Parser { tokens, pos: 0, depth: 0, functions: vec![], errors: vec![] };
+    let mut statements = vec![];
+    while parser.pos < tokens.len() {
+        statements.append(&mut parser.parse_statements());
+        // A block can only end early on an unmatched `}`
+        if let Some(stray) = parser.next() {
+            parser.errors.push(CompileError::Parse(stray.span, "unexpected `}` without a matching `{`".to_string()));
+        }
+    }
+    if parser.errors.is_empty() {
+        Ok(Program { statements, functions: parser.functions })
+    } else {
+        Err(parser.errors)
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [SpannedToken],
+    pos: usize,
+    /// How many blocks deep the parser is. Functions can only be defined at depth 0.
+    depth: usize,
+    functions: Vec<Function>,
+    errors: Vec<CompileError>,
+}
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a SpannedToken> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<&'a SpannedToken> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+    /// Span of the last token, where errors about running out of tokens are reported.
+    fn eof_span(&self) -> Span {
+        self.tokens.last().map(|t| t.span).unwrap_or_default()
+    }
+    fn expect(&mut self, token: Token, what: &str) -> Result<Span, CompileError> {
+        match self.next() {
+            Some(t) if t.token == token => Ok(t.span),
+            Some(t) => Err(CompileError::Parse(t.span, format!("expected {what}, found `{}`", t.token))),
+            None => Err(CompileError::Parse(self.eof_span(), format!("expected {what}, found end of file"))),
+        }
+    }
+    fn expect_identifier(&mut self, what: &str) -> Result<(String, Span), CompileError> {
+        match self.next() {
+            Some(SpannedToken { token: Token::Identifier(ident), span }) => Ok((ident.clone(), *span)),
+            Some(t) => Err(CompileError::Parse(t.span, format!("expected {what}, found `{}`", t.token))),
+            None => Err(CompileError::Parse(self.eof_span(), format!("expected {what}, found end of file"))),
+        }
+    }
+    /// Takes every token up to the next `;`, returning them along with the span of the `;`, which is skipped.
+    fn until_semicolon(&mut self) -> Result<(&'a [SpannedToken], Span), CompileError> {
+        let start = self.pos;
+        let semicolon = loop {
+            match self.peek() {
+                Some(SpannedToken { token: Token::Semicolon, span }) => break *span,
+                Some(SpannedToken { token: Token::LBrace | Token::RBrace, span }) => {
+                    return Err(CompileError::Parse(*span, "expected `;` before this".to_string()));
+                }
+                Some(_) => self.pos += 1,
+                None => return Err(CompileError::Parse(self.eof_span(), "expected `;` at end of file".to_string())),
+            }
+        };
+        self.pos += 1;
+        Ok((&self.tokens[start..self.pos - 1], semicolon))
+    }
+    /// Skips past the statement an error happened in, stopping after a `;` or before the `}` closing the current block.
+    fn recover(&mut self) {
+        let mut nesting = 0;
+        while let Some(t) = self.peek() {
+            match t.token {
+                Token::Semicolon if nesting == 0 => {
+                    self.pos += 1;
+                    return;
+                }
+                Token::LBrace => nesting += 1,
+                Token::RBrace if nesting == 0 => return,
+                Token::RBrace => nesting -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+    /// Parses statements until the end of the script or a `}`, which is left for the caller.
+    fn parse_statements(&mut self) -> Vec<Statement> {
+        let mut statements = vec![];
+        while let Some(t) = self.peek() {
+            if t.token == Token::RBrace {
+                break;
+            }
+            match self.parse_statement() {
+                Ok(Some(statement)) => statements.push(statement),
+                Ok(None) => {}
+                Err(e) => {
+                    self.errors.push(e);
+                    self.recover();
+                }
+            }
+        }
+        statements
+    }
+    /// Parses `{ ... }`, returning the statements and the span of the closing brace.
+    fn parse_block(&mut self) -> Result<(Vec<Statement>, Span), CompileError> {
+        self.expect(Token::LBrace, "`{`")?;
+        self.depth += 1;
+        let statements = self.parse_statements();
+        self.depth -= 1;
+        let end = self.expect(Token::RBrace, "`}`")?;
+        Ok((statements, end))
+    }
+    /// Parses `( ... )` around a condition.
+    fn parse_condition(&mut self) -> Result<Expression, CompileError> {
+        let lparen = self.expect(Token::LParen, "`(` before the condition")?;
+        let start = self.pos;
+        let mut nesting = 0;
+        loop {
+            match self.next().map(|t| &t.token) {
+                Some(Token::LParen) => nesting += 1,
+                Some(Token::RParen) if nesting == 0 => break,
+                Some(Token::RParen) => nesting -= 1,
+                Some(Token::LBrace | Token::RBrace | Token::Semicolon) | None => {
+                    return Err(CompileError::Parse(lparen, "unclosed `(` around the condition".to_string()));
+                }
+                _ => {}
+            }
+        }
+        Expression::make_ast(&self.tokens[start..self.pos - 1], lparen)
+    }
+    fn parse_statement(&mut self) -> Result<Option<Statement>, CompileError> {
+        let Some(first) = self.peek() else {
+            return Ok(None);
+        };
+        match &first.token {
+            Token::Semicolon => {
+                self.pos += 1;
+                Ok(None)
+            }
+            Token::If => self.parse_if().map(Some),
+            Token::While => {
+                self.pos += 1;
+                let cond = self.parse_condition()?;
+                let (body, end) = self.parse_block()?;
+                Ok(Some(Statement { kind: StatementKind::While(cond, body), span: first.span.to(end) }))
+            }
+            Token::Break | Token::Continue => {
+                self.pos += 1;
+                let (kind, keyword) = if first.token == Token::Break {
+                    (StatementKind::Break, "break")
+                } else {
+                    (StatementKind::Continue, "continue")
+                };
+                let end = self.expect(Token::Semicolon, &format!("`;` after `{keyword}`"))?;
+                Ok(Some(Statement { kind, span: first.span.to(end) }))
+            }
+            Token::Return => {
+                self.pos += 1;
+                let (value, end) = self.until_semicolon()?;
+                let value = if value.is_empty() { None } else { Some(Expression::make_ast(value, first.span)?) };
+                Ok(Some(Statement { kind: StatementKind::Return(value), span: first.span.to(end) }))
+            }
+            Token::Fn | Token::Inline if self.depth > 0 => {
+                Err(CompileError::Parse(first.span, "functions can only be defined at the top level".to_string()))
+            }
+            Token::Fn => {
+                let function = self.parse_function()?;
+                self.functions.push(function);
+                Ok(None)
+            }
+            Token::Inline => {
+                self.pos += 1;
+                let mut function = self.parse_function()?;
+                function.ftype = FunctionType::Macro;
+                function.span = first.span.to(function.span);
+                self.functions.push(function);
+                Ok(None)
+            }
+            Token::Else => Err(CompileError::Parse(first.span, "`else` without an `if`".to_string())),
+            // Inline logic stands on its own, so the semicolon after it is optional.
+            Token::InlineAsm(_) if !matches!(self.tokens.get(self.pos + 1).map(|t| &t.token), Some(Token::BinaryOp(_))) => {
+                self.pos += 1;
+                if let Some(Token::Semicolon) = self.peek().map(|t| &t.token) {
+                    self.pos += 1;
+                }
+                let expr = Expression::make_ast(std::slice::from_ref(first), first.span)?;
+                Ok(Some(Statement { kind: StatementKind::Expression(expr), span: first.span }))
+            }
+            _ => self.parse_simple().map(Some),
+        }
+    }
+    /// Parses `if (cond) { ... }` with any `else` or `else if` after it.
+    fn parse_if(&mut self) -> Result<Statement, CompileError> {
+        let start = self.expect(Token::If, "`if`")?;
+        let cond = self.parse_condition()?;
+        let (then, mut end) = self.parse_block()?;
+        let mut otherwise = None;
+        if let Some(Token::Else) = self.peek().map(|t| &t.token) {
+            self.pos += 1;
+            if let Some(Token::If) = self.peek().map(|t| &t.token) {
+                let else_if = self.parse_if()?;
+                end = else_if.span;
+                otherwise = Some(vec![else_if]);
+            } else {
+                let (block, block_end) = self.parse_block()?;
+                end = block_end;
+                otherwise = Some(block);
+            }
+        }
+        Ok(Statement { kind: StatementKind::If(cond, then, otherwise), span: start.to(end) })
+    }
+    /// Parses `fn name(params) { ... }`.
+    fn parse_function(&mut self) -> Result<Function, CompileError> {
+        let start = self.expect(Token::Fn, "`fn`")?;
+        let (fname, fname_span) = self.expect_identifier("a function name")?;
+        if STATEMENT_BUILTINS.contains(&&fname[..]) {
+            return Err(CompileError::Parse(fname_span, format!("`{fname}` is a builtin and can't be redefined")));
+        }
+        self.expect(Token::LParen, "`(` before the parameters")?;
+        let mut params = vec![];
+        if let Some(Token::RParen) = self.peek().map(|t| &t.token) {
+            self.pos += 1;
+        } else {
+            loop {
+                params.push(self.expect_identifier("a parameter name")?);
+                match self.next() {
+                    Some(SpannedToken { token: Token::Comma, .. }) => {}
+                    Some(SpannedToken { token: Token::RParen, .. }) => break,
+                    Some(t) => return Err(CompileError::Parse(t.span, format!("expected `,` or `)`, found `{}`", t.token))),
+                    None => return Err(CompileError::Parse(self.eof_span(), "expected `)`, found end of file".to_string())),
+                }
+            }
+        }
+        // Parameters with the same name would be passed in the same variable
+        for (idx, (param, span)) in params.iter().enumerate() {
+            if params[..idx].iter().any(|(p, _)| p == param) {
+                return Err(CompileError::Parse(*span, format!("parameter `{param}` is defined more than once")));
+            }
+        }
+        let (mut body, end) = self.parse_block()?;
+        let renames = params.iter()
+            .map(|(param, _)| (param.clone(), Value::Identifier(Function::param_var(&fname, param))))
+            .collect::<HashMap<String, Value>>();
+        for statement in &mut body {
+            statement.substitute(&renames);
+        }
+        Ok(Function {
+            ftype: FunctionType::Intern,
+            params: params.into_iter().map(|(param, _)| param).collect(),
+            fname,
+            body,
+            span: start.to(end),
+        })
+    }
+    /// Parses an assignment or expression ending in a semicolon.
+    fn parse_simple(&mut self) -> Result<Statement, CompileError> {
+        let (stmt, semicolon) = self.until_semicolon()?;
+        let span = stmt.first().map_or(semicolon, |t| t.span).to(semicolon);
+        let kind = match stmt {
+            [SpannedToken { token: Token::Identifier(ident), .. }, SpannedToken { token: Token::Assignment, span: eq }, expr @ ..] => {
+                StatementKind::Assignment(ident.clone(), Box::new(Expression::make_ast(expr, *eq)?))
+            }
+            [SpannedToken { token: Token::Builtin(name), span }, SpannedToken { token: Token::Assignment, .. }, ..] => {
+                return Err(CompileError::Parse(*span, format!("`{name}` is a builtin and can't be assigned to")));
+            }
+            expr => match Expression::make_ast(expr, span)? {
+                Expression { kind: ExpressionKind::Call(ident, args), span } if ident == "print" => StatementKind::Print(print_pieces(args, span)?),
+                Expression { kind: ExpressionKind::Call(ident, args), span } if ident == "printflush" => {
+                    let [block]: [Expression; 1] = args.try_into().map_err(|args: Vec<Expression>| {
+                        CompileError::Parse(span, format!("`printflush` takes 1 argument but {} {} supplied", args.len(), if args.len() == 1 { "was" } else { "were" }))
+                    })?;
+                    StatementKind::PrintFlush(block)
+                }
+                expr => StatementKind::Expression(expr),
+            },
+        };
+        Ok(Statement { kind, span })
+    }
+}
+
+/// The expressions printed by `print(args)`, one `print` each. If the first argument is a string, it's a format string
+/// whose `{}`s are replaced by the rest of the arguments, and `{{` and `}}` print a brace.
+fn print_pieces(mut args: Vec<Expression>, span: Span) -> Result<Vec<Expression>, CompileError> {
+    let Some(Expression { kind: ExpressionKind::Value(Value::Str(format)), span: format_span }) = args.first().cloned() else {
+        if args.is_empty() {
+            return Err(CompileError::Parse(span, "`print` needs something to print".to_string()));
+        }
+        return Ok(args);
+    };
+    let literal = |text: &mut String| Expression::new(ExpressionKind::Value(Value::Str(std::mem::take(text))), format_span);
+    let supplied = args.len() - 1;
+    let mut values = args.drain(1..);
+    let mut pieces = vec![];
+    let mut text = String::new();
+    let mut placeholders = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                text.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                placeholders += 1;
+                if !text.is_empty() {
+                    pieces.push(literal(&mut text));
+                }
+                pieces.extend(values.next());
+            }
+            ('{' | '}', _) => {
+                return Err(CompileError::Parse(format_span, format!("unmatched `{c}` in format string, use `{c}{c}` to print it")));
+            }
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(literal(&mut text));
+    }
+    if placeholders != supplied {
+        return Err(CompileError::Parse(
+            span,
+            format!("format string has {placeholders} `{{}}` but {supplied} argument{} supplied", if supplied == 1 { " was" } else { "s were" }),
+        ));
+    }
+    Ok(pieces)
+}
+
+/// Every function each function can end up calling, directly or through other functions.
+pub fn call_graph(program: &Program) -> HashMap<String, HashSet<String>> {
+    let direct = program.functions.iter().map(|function| {
+        let mut calls = vec![];
+        for statement in &function.body {
+            statement.calls(&mut calls);
+        }
+        (function.fname.clone(), calls.into_iter().map(|(callee, _)| callee.to_string()).collect::<HashSet<String>>())
+    }).collect::<HashMap<_, _>>();
+
+    let mut graph = HashMap::new();
+    for fname in direct.keys() {
+        let mut reached = HashSet::new();
+        let mut stack = vec![fname];
+        while let Some(caller) = stack.pop() {
+            for callee in direct.get(caller).into_iter().flatten() {
+                if reached.insert(callee.clone()) {
+                    stack.push(callee);
+                }
+            }
+        }
+        graph.insert(fname.clone(), reached);
+    }
+    graph
+}
+
+/// Reports code that parses fine but can't be compiled, such as calls to functions that don't exist.
+/// Recursion is only allowed with a call stack to keep each call's state in.
+pub fn check_program(program: &Program, stack: bool) -> Result<(), Vec<CompileError>> {
+    let mut errors = vec![];
+    let mut functions = HashMap::new();
+    for function in &program.functions {
+        if functions.contains_key(&function.fname) {
+            errors.push(CompileError::Semantic(function.span, format!("function `{}` is defined more than once", function.fname)));
+        } else {
+            functions.insert(function.fname.clone(), function.params.len());
+        }
+    }
+    for statement in &program.statements {
+        statement.check(&functions, false, &mut errors);
+    }
+    let graph = call_graph(program);
+    for function in &program.functions {
+        let mut calls = vec![];
+        for statement in &function.body {
+            statement.calls(&mut calls);
+        }
+        if function.ftype == FunctionType::Macro && graph[&function.fname].contains(&function.fname) {
+            // Without a direct call to itself, name the function the recursion goes through
+            let through = calls.iter().map(|(callee, _)| *callee)
+                .find(|callee| graph.get(*callee).is_some_and(|reached| reached.contains(&function.fname)));
+            let message = match through {
+                Some(callee) if calls.iter().all(|(callee, _)| *callee != function.fname) => {
+                    format!("inline function `{}` can end up calling itself through `{callee}`", function.fname)
+                }
+                _ => format!("inline function `{}` can't call itself", function.fname),
+            };
+            errors.push(CompileError::Semantic(function.span, message));
+            continue;
+        }
+        for statement in &function.body {
+            statement.check(&functions, true, &mut errors);
+        }
+        if stack {
+            continue;
+        }
+        for (callee, span) in calls {
+            if graph.get(callee).is_some_and(|reached| reached.contains(&function.fname)) {
+                errors.push(CompileError::Semantic(
+                    span,
+                    format!("recursive call to `{callee}` needs a call stack, pass `--stack <memory cell>` to enable one"),
+                ));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Computes everything in the program that can be known at compile time. See `Expression::fold`.
+pub fn fold_program(program: &mut Program) {
+    let functions = program.functions.iter_mut().flat_map(|function| function.body.iter_mut());
+    for statement in program.statements.iter_mut().chain(functions) {
+        statement.fold();
+    }
+}
+
+/// State shared by the code generation of every statement.
+#[derive(Default)]
+pub struct Context {
+    labels: usize,
+    /// The `continue` and `break` labels of every loop being generated, innermost last.
+    pub loops: Vec<(Label, Label)>,
+    /// The parameters of every function that isn't inline.
+    pub functions: HashMap<String, Vec<String>>,
+    /// Every inline function.
+    pub macros: HashMap<String, Function>,
+    /// The inline function and the label after it for every inline function call being generated, innermost last.
+    pub inlines: Vec<(String, Label)>,
+    /// The function being generated, if not the main program.
+    function: Option<String>,
+    /// The memory cell or bank recursive calls keep their state in, if recursion is enabled.
+    pub stack: Option<String>,
+    /// Every function each function can end up calling.
+    reaches: HashMap<String, HashSet<String>>,
+    /// The registers below this hold values of the code an inline function is being copied into,
+    /// so statements only use the ones after it.
+    pub registers: usize,
+}
+impl Context {
+    /// The variable holding the address of the next free slot on the call stack.
+    pub const STACK_POINTER: &'static str = "stack:sp";
+
+    /// The memory block and the variables a call to `callee` has to save and restore around itself,
+    /// if it can re-enter the function being generated. The result is computed into `storage`.
+    pub fn saved_across_call(&self, callee: &str, storage: &VarStorage) -> Option<(String, Vec<String>)> {
+        let block = self.stack.clone()?;
+        let caller = self.function.as_ref()?;
+        if !self.reaches.get(callee).is_some_and(|reached| reached.contains(caller)) {
+            return None;
+        }
+        let mut saved = vec![Function::return_address(caller)];
+        saved.extend(self.functions[caller].iter().map(|param| Function::param_var(caller, param)));
+        saved.extend(storage.live_registers().iter().map(VarStorage::to_string));
+        Some((block, saved))
+    }
+    /// The register `n` places after the ones statements can't touch.
+    pub fn register(&self, n: usize) -> VarStorage {
+        VarStorage::Register(self.registers + n)
+    }
+    /// Storage for a statement to compute into the variable `name`.
+    pub fn variable(&self, name: String) -> VarStorage {
+        VarStorage::Identifier(name, self.registers)
+    }
+    /// Makes a new label that no other jump uses.
+    pub fn label(&mut self) -> Label {
+        self.labels += 1;
+        Label::Local(self.labels - 1)
+    }
+}
+
+/// Generates the main program followed by every function. The main program ends with `end` so it doesn't run into them.
+pub fn generate_program(program: &Program, ctx: &mut Context) -> Result<Vec<IR>, Vec<CompileError>> {
+    for function in &program.functions {
+        match function.ftype {
+            FunctionType::Intern => { ctx.functions.insert(function.fname.clone(), function.params.clone()); }
+            FunctionType::Macro => { ctx.macros.insert(function.fname.clone(), function.clone()); }
+        }
+    }
+    ctx.reaches = call_graph(program);
+    let mut errors = vec![];
+    let mut ir = vec![];
+    if ctx.stack.is_some() {
+        ir.push(IR::Set(Context::STACK_POINTER.to_string(), "0".to_string()));
+    }
+    match generate_marked(&program.statements, ctx) {
+        Ok(mut main) => ir.append(&mut main),
+        Err(mut e) => errors.append(&mut e),
+    }
+    if !ctx.functions.is_empty() {
+        ir.push(IR::Label(Label::Statement(None)));
+        ir.push(IR::End);
+    }
+    for function in program.functions.iter().filter(|function| function.ftype == FunctionType::Intern) {
+        ctx.function = Some(function.fname.clone());
+        ir.push(IR::Label(Label::Function(function.fname.clone())));
+        match generate_marked(&function.body, ctx) {
+            Ok(mut body) => ir.append(&mut body),
+            Err(mut e) => errors.append(&mut e),
+        }
+        ir.push(IR::Label(Label::Statement(None)));
+        ir.push(IR::Set("@counter".to_string(), Function::return_address(&function.fname)));
+        ctx.function = None;
+    }
+    if errors.is_empty() {
+        Ok(ir)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Generates the IR of every statement in order, collecting the errors of all that fail.
+pub fn generate_statements(statements: &[Statement], ctx: &mut Context) -> Result<Vec<IR>, Vec<CompileError>> {
+    let mut ir = vec![];
+    let mut errors = vec![];
+    for statement in statements {
+        match statement.generate_ir(ctx) {
+            Ok(mut statement_ir) => ir.append(&mut statement_ir),
+            Err(mut e) => errors.append(&mut e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(ir)
+    } else {
+        Err(errors)
+    }
+}
+
+/// `generate_statements`, with each statement's code starting with a `Label::Statement` so its size can be reported.
+fn generate_marked(statements: &[Statement], ctx: &mut Context) -> Result<Vec<IR>, Vec<CompileError>> {
+    let mut ir = vec![];
+    let mut errors = vec![];
+    for statement in statements {
+        match statement.generate_ir(ctx) {
+            Ok(mut statement_ir) => {
+                ir.push(IR::Label(Label::Statement(Some(statement.span))));
+                ir.append(&mut statement_ir);
+            }
+            Err(mut e) => errors.append(&mut e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(ir)
+    } else {
+        Err(errors)
+    }
+}
+
+impl Statement {
+    /// The expressions and blocks directly inside this statement.
+    fn children(&self) -> (Vec<&Expression>, Vec<&Vec<Statement>>) {
+        match &self.kind {
+            StatementKind::Assignment(_, expr) => (vec![expr], vec![]),
+            StatementKind::Expression(expr) | StatementKind::Return(Some(expr)) => (vec![expr], vec![]),
+            StatementKind::If(cond, then, otherwise) => (vec![cond], [Some(then), otherwise.as_ref()].into_iter().flatten().collect()),
+            StatementKind::While(cond, body) => (vec![cond], vec![body]),
+            StatementKind::Print(values) => (values.iter().collect(), vec![]),
+            StatementKind::PrintFlush(block) => (vec![block], vec![]),
+            StatementKind::Break | StatementKind::Continue | StatementKind::Return(None) => (vec![], vec![]),
+        }
+    }
+    /// The expressions and blocks directly inside this statement, for changing them.
+    fn children_mut(&mut self) -> (Vec<&mut Expression>, Vec<&mut Vec<Statement>>) {
+        match &mut self.kind {
+            StatementKind::Assignment(_, expr) => (vec![expr], vec![]),
+            StatementKind::Expression(expr) | StatementKind::Return(Some(expr)) => (vec![expr], vec![]),
+            StatementKind::If(cond, then, otherwise) => (vec![cond], [Some(then), otherwise.as_mut()].into_iter().flatten().collect()),
+            StatementKind::While(cond, body) => (vec![cond], vec![body]),
+            StatementKind::Print(values) => (values.iter_mut().collect(), vec![]),
+            StatementKind::PrintFlush(block) => (vec![block], vec![]),
+            StatementKind::Break | StatementKind::Continue | StatementKind::Return(None) => (vec![], vec![]),
+        }
+    }
+    /// Folds the constants in every expression in this statement. See `Expression::fold`.
+    pub fn fold(&mut self) {
+        let (exprs, blocks) = self.children_mut();
+        for expr in exprs {
+            expr.fold();
+        }
+        for statement in blocks.into_iter().flatten() {
+            statement.fold();
+        }
+    }
+    /// Reports calls to functions that don't exist or with the wrong number of arguments, and misplaced `return`s.
+    fn check(&self, functions: &HashMap<String, usize>, in_function: bool, errors: &mut Vec<CompileError>) {
+        if let StatementKind::Return(_) = self.kind {
+            if !in_function {
+                errors.push(CompileError::Semantic(self.span, "`return` outside of a function".to_string()));
+            }
+        }
+        let (exprs, blocks) = self.children();
+        for expr in exprs {
+            expr.check_calls(functions, errors);
+        }
+        for statement in blocks.into_iter().flatten() {
+            statement.check(functions, in_function, errors);
+        }
+    }
+    /// Collects the name and span of every call in this statement.
+    pub fn calls<'a>(&'a self, calls: &mut Vec<(&'a str, Span)>) {
+        let (exprs, blocks) = self.children();
+        for expr in exprs {
+            expr.calls(calls);
+        }
+        for statement in blocks.into_iter().flatten() {
+            statement.calls(calls);
+        }
+    }
+    /// Replaces variables with other variables or constants where they're read, and renames them where they're assigned.
+    /// Variables that are assigned must only be replaced with other variables.
+    pub fn substitute(&mut self, values: &HashMap<String, Value>) {
+        match &mut self.kind {
+            StatementKind::Assignment(ident, expr) => {
+                if let Some(Value::Identifier(renamed)) = values.get(ident) {
+                    *ident = renamed.clone();
+                }
+                expr.substitute(values);
+            }
+            StatementKind::Expression(expr) | StatementKind::Return(Some(expr)) | StatementKind::PrintFlush(expr) => expr.substitute(values),
+            StatementKind::Print(printed) => {
+                for expr in printed {
+                    expr.substitute(values);
+                }
+            }
+            StatementKind::If(cond, then, otherwise) => {
+                cond.substitute(values);
+                for statement in then.iter_mut().chain(otherwise.iter_mut().flatten()) {
+                    statement.substitute(values);
+                }
+            }
+            StatementKind::While(cond, body) => {
+                cond.substitute(values);
+                for statement in body {
+                    statement.substitute(values);
+                }
+            }
+            StatementKind::Break | StatementKind::Continue | StatementKind::Return(None) => {}
+        }
+    }
+    /// Whether this statement assigns to `var` anywhere.
+    pub fn assigns(&self, var: &str) -> bool {
+        if let StatementKind::Assignment(ident, _) = &self.kind {
+            if ident == var {
+                return true;
+            }
+        }
+        self.children().1.into_iter().flatten().any(|statement| statement.assigns(var))
+    }
+    pub fn generate_ir(&self, ctx: &mut Context) -> Result<Vec<IR>, Vec<CompileError>> {
+        match &self.kind {
+            StatementKind::Assignment(ident, expr) => expr.generate_ir(&ctx.variable(ident.clone()), ctx).map_err(|e| vec![e]),
+            // A call's result isn't needed when it's called on its own
+            StatementKind::Expression(Expression { kind: ExpressionKind::Call(ident, args), .. }) => {
+                Expression::generate_call(ident, args, &ctx.register(0), ctx).map_err(|e| vec![e])
+            }
+            StatementKind::Expression(expr) => expr.generate_ir(&ctx.register(0), ctx).map_err(|e| vec![e]),
+            StatementKind::If(cond, then, otherwise) => {
+                // jump ELSE if !cond; then; jump END; ELSE: otherwise; END:
+                let else_label = ctx.label();
+                let mut ir = cond.generate_jump(else_label.clone(), false, ctx).map_err(|e| vec![e])?;
+                ir.append(&mut generate_statements(then, ctx)?);
+                match otherwise {
+                    Some(otherwise) => {
+                        let end_label = ctx.label();
+                        ir.push(IR::Jump(end_label.clone(), JumpCond::Always));
+                        ir.push(IR::Label(else_label));
+                        ir.append(&mut generate_statements(otherwise, ctx)?);
+                        ir.push(IR::Label(end_label));
+                    }
+                    None => ir.push(IR::Label(else_label)),
+                }
+                Ok(ir)
+            }
+            StatementKind::While(cond, body) => {
+                // The condition is checked at the bottom so each iteration only takes one jump:
+                // jump END if !cond; TOP: body; CONTINUE: jump TOP if cond; END:
+                // A condition that's always true needs neither the guard nor a comparison.
+                let always = matches!(cond.kind, ExpressionKind::Value(Value::Num(n)) if n != 0.0);
+                let (top, cont, end) = (ctx.label(), ctx.label(), ctx.label());
+                let mut ir = vec![];
+                if !always {
+                    ir.append(&mut cond.generate_jump(end.clone(), false, ctx).map_err(|e| vec![e])?);
+                }
+                ir.push(IR::Label(top.clone()));
+                ctx.loops.push((cont.clone(), end.clone()));
+                let body = generate_statements(body, ctx);
+                ctx.loops.pop();
+                ir.append(&mut body?);
+                ir.push(IR::Label(cont));
+                if always {
+                    ir.push(IR::Jump(top, JumpCond::Always));
+                } else {
+                    ir.append(&mut cond.generate_jump(top, true, ctx).map_err(|e| vec![e])?);
+                }
+                ir.push(IR::Label(end));
+                Ok(ir)
+            }
+            StatementKind::Break | StatementKind::Continue => {
+                let Some((cont, end)) = ctx.loops.last() else {
+                    let keyword = if let StatementKind::Break = self.kind { "break" } else { "continue" };
+                    return Err(vec![CompileError::Semantic(self.span, format!("`{keyword}` outside of a loop"))]);
+                };
+                let label = if let StatementKind::Break = self.kind { end } else { cont };
+                Ok(vec![IR::Jump(label.clone(), JumpCond::Always)])
+            }
+            StatementKind::Print(values) => {
+                let mut ir = vec![];
+                for value in values {
+                    let (mut value_ir, operand) = value.generate_operand(&ctx.register(0), ctx).map_err(|e| vec![e])?;
+                    ir.append(&mut value_ir);
+                    ir.push(IR::Print(operand));
+                }
+                Ok(ir)
+            }
+            StatementKind::PrintFlush(block) => {
+                let (mut ir, operand) = block.generate_operand(&ctx.register(0), ctx).map_err(|e| vec![e])?;
+                ir.push(IR::PrintFlush(operand));
+                Ok(ir)
+            }
+            StatementKind::Return(value) => {
+                if let Some((fname, end)) = ctx.inlines.last().cloned() {
+                    let mut ir = match value {
+                        Some(value) => value.generate_ir(&ctx.variable(Function::return_value(&fname)), ctx).map_err(|e| vec![e])?,
+                        None => vec![],
+                    };
+                    ir.push(IR::Jump(end, JumpCond::Always));
+                    return Ok(ir);
+                }
+                let Some(fname) = ctx.function.clone() else {
+                    return Err(vec![CompileError::Semantic(self.span, "`return` outside of a function".to_string())]);
+                };
+                let mut ir = match value {
+                    Some(value) => value.generate_ir(&ctx.variable(Function::return_value(&fname)), ctx).map_err(|e| vec![e])?,
+                    None => vec![],
+                };
+                ir.push(IR::Set("@counter".to_string(), Function::return_address(&fname)));
+                Ok(ir)
+            }
+        }
+    }
+}